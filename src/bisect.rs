@@ -0,0 +1,285 @@
+use config::{Benchmark, Config};
+use git2;
+use history::History;
+use logs::LogStore;
+use process::{CancelToken, Timeouts};
+use repo::Workspace;
+use taste::{self, BenchmarkResult, TastingResult};
+use Commit;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Maximum number of first-parent commits between a good and a bad commit that we bisect.
+const MAX_BISECT_RANGE: usize = 1000;
+
+/// The commit that introduced a regression, as determined by bisection.
+#[derive(Clone, Debug)]
+pub struct Culprit {
+    pub commit: Commit,
+    /// Name of the commit's author, as recorded in git.
+    pub author: String,
+    /// Email address of the commit's author, as recorded in git.
+    pub email: String,
+    pub benchmark: String,
+    pub metric: String,
+    /// Relative change of the metric at the culprit compared to the last good commit, or `None`
+    /// if the benchmark failed to report it there.
+    pub change: Option<f64>,
+}
+
+/// Returns the first-parent commits after `good` up to and including `bad`, oldest first.
+fn first_parent_range(
+    repo: &git2::Repository,
+    good: git2::Oid,
+    bad: git2::Oid,
+) -> Result<Vec<git2::Oid>, String> {
+    let mut range = Vec::new();
+    let mut cur = bad;
+    while cur != good {
+        if range.len() >= MAX_BISECT_RANGE {
+            return Err(format!(
+                "{} is more than {} first-parent commits behind {}",
+                good, MAX_BISECT_RANGE, bad
+            ));
+        }
+        range.push(cur);
+        cur = repo.find_commit(cur)
+            .and_then(|c| c.parent_id(0))
+            .map_err(|_| format!("{} is not a first-parent ancestor of {}", good, bad))?;
+    }
+    range.reverse();
+    Ok(range)
+}
+
+/// Bisects the first-parent range between `good` and `bad` for the commit that made `metric` of
+/// `bench` regress relative to the `good_samples` recorded at `good`; `bad_change` is the change
+/// already observed at `bad`. Each step builds the commit and runs only `bench`, whose results are
+/// recorded in `history`; commits that fail to build, or at which the benchmark fails or stops
+/// reporting the metric, count as bad. `bench_lock` is held while each step benchmarks, if given.
+pub fn bisect(
+    ws: &Workspace,
+    history: &Mutex<History>,
+    cfg: &Config,
+    bench: &Benchmark,
+    metric: &str,
+    good: git2::Oid,
+    good_samples: &[f64],
+    bad: git2::Oid,
+    bad_change: f64,
+    timeouts: &Timeouts,
+    logs: &LogStore,
+    push_ref: Option<&str>,
+    cancel: &CancelToken,
+    bench_lock: Option<&Mutex<()>>,
+) -> Result<Culprit, String> {
+    let range = first_parent_range(&ws.repo, good, bad)?;
+    if range.is_empty() {
+        return Err(format!("nothing to bisect between {} and itself", good));
+    }
+    println!(
+        "bisecting {} across {} commits between {} and {}",
+        metric,
+        range.len(),
+        good,
+        bad
+    );
+
+    // invariant: range[hi] is bad, and everything before range[lo] is good
+    let (mut lo, mut hi) = (0, range.len() - 1);
+    let mut hi_change = Some(bad_change);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        ws.checkout_commit(&range[mid])?;
+        let commit_logs = logs.for_commit(&ws.remote_url, push_ref, range[mid]);
+        let built = taste::update_and_build(&ws.path, timeouts.build, &commit_logs, cancel)?;
+        let (status, res) = if built.success() {
+            // the lock guards no data, so it's still usable if a tasting panicked while holding it
+            let _bench_guard = bench_lock.map(|l| l.lock().unwrap_or_else(|e| e.into_inner()));
+            taste::benchmark(
+                &ws.path,
+                cfg,
+                bench,
+                &commit_logs,
+                |m: &str| if m == metric {
+                    Some(good_samples.to_vec())
+                } else {
+                    None
+                },
+                timeouts.bench,
+                cancel,
+            )?
+        } else {
+            (built, HashMap::new())
+        };
+        if cancel.is_cancelled() {
+            return Err(String::from("bisection was cancelled"));
+        }
+        if status.success() {
+            taste::record_benchmark(&mut history.lock().unwrap(), range[mid], bench, &res);
+        }
+
+        let regressed = match res.get(metric) {
+            _ if !status.success() => None,
+            Some(&BenchmarkResult::Regression(_, c)) => Some((true, c)),
+            Some(&BenchmarkResult::Improvement(_, c)) | Some(&BenchmarkResult::Neutral(_, c)) => {
+                Some((false, c))
+            }
+            None => None,
+        };
+        match regressed {
+            Some((true, c)) => {
+                println!("{} is bad ({:+.2}%)", range[mid], c * 100.0);
+                hi_change = Some(c);
+                hi = mid;
+            }
+            Some((false, c)) => {
+                println!("{} is good ({:+.2}%)", range[mid], c * 100.0);
+                lo = mid + 1;
+            }
+            None => {
+                println!("{} failed to produce {}, treating it as bad", range[mid], metric);
+                hi_change = None;
+                hi = mid;
+            }
+        }
+    }
+
+    let c = ws.repo
+        .find_commit(range[lo])
+        .map_err(|e| format!("failed to look up {}: {}", range[lo], e))?;
+    let author = c.author();
+    Ok(Culprit {
+        commit: Commit {
+            id: c.id(),
+            msg: String::from(c.message().unwrap_or("")),
+            url: format!("{}/commit/{}", ws.remote_url, c.id()),
+        },
+        author: String::from(author.name().unwrap_or("unknown")),
+        email: String::from(author.email().unwrap_or("")),
+        benchmark: bench.name.clone(),
+        metric: String::from(metric),
+        change: hi_change,
+    })
+}
+
+/// Bisects every benchmark in `res` that regressed relative to a baseline further back than the
+/// tasted commit's parent, looking at its worst-regressed metric. `bench_lock` is held while
+/// each bisection step benchmarks, if given, so that other jobs can run between steps.
+pub fn bisect_regressions(
+    ws: &Workspace,
+    history: &Mutex<History>,
+    cfg: &Config,
+    res: &TastingResult,
+    timeouts: &Timeouts,
+    logs: &LogStore,
+    push_ref: Option<&str>,
+    cancel: &CancelToken,
+    bench_lock: Option<&Mutex<()>>,
+) -> Vec<Culprit> {
+    let parent = ws.repo
+        .find_commit(res.commit.id)
+        .and_then(|c| c.parent_id(0))
+        .ok();
+    let results = match res.results {
+        None => return vec![],
+        Some(ref r) => r,
+    };
+
+    let mut culprits = Vec::new();
+    for &(ref bench, ref status, ref metrics) in results {
+        if !status.success() {
+            continue;
+        }
+        let worst = metrics
+            .iter()
+            .filter_map(|(k, v)| match *v {
+                BenchmarkResult::Regression(_, c) => Some((k, c)),
+                _ => None,
+            })
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+        let (metric, change) = match worst {
+            None => continue,
+            Some(w) => w,
+        };
+        let baseline = res.baselines.get(&bench.name).and_then(|b| b.get(metric));
+        let (good, good_samples) = match baseline {
+            // nothing to bisect if the baseline is our parent, or the commit itself
+            Some(&(oid, ref s)) if Some(oid) != parent && oid != res.commit.id => {
+                (oid, &s.samples)
+            }
+            _ => continue,
+        };
+
+        match bisect(
+            ws,
+            history,
+            cfg,
+            bench,
+            metric,
+            good,
+            good_samples,
+            res.commit.id,
+            change,
+            timeouts,
+            logs,
+            push_ref,
+            cancel,
+            bench_lock,
+        ) {
+            Ok(culprit) => {
+                println!("{} is the first bad commit for {}", culprit.commit.id, metric);
+                culprits.push(culprit);
+            }
+            Err(e) => println!("failed to bisect {}: {}", metric, e),
+        }
+    }
+    culprits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    /// Creates a repository with a first-parent chain of `n` commits, returned oldest first.
+    fn chain(name: &str, n: usize) -> (git2::Repository, Vec<git2::Oid>) {
+        let path = env::temp_dir().join(format!("taster-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        let repo = git2::Repository::init(&path).unwrap();
+        let mut oids = Vec::new();
+        {
+            let sig = git2::Signature::now("Taster", "taster@example.com").unwrap();
+            let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+            for i in 0..n {
+                let parents = oids.last().map(|p| repo.find_commit(*p).unwrap());
+                let parents: Vec<_> = parents.iter().collect();
+                let msg = format!("commit {}", i);
+                oids.push(repo.commit(None, &sig, &sig, &msg, &tree, &parents).unwrap());
+            }
+        }
+        (repo, oids)
+    }
+
+    #[test]
+    fn first_parent_ranges() {
+        let (repo, oids) = chain("first-parent-range", 4);
+        assert_eq!(first_parent_range(&repo, oids[0], oids[3]).unwrap(), &oids[1..]);
+        assert_eq!(first_parent_range(&repo, oids[2], oids[3]).unwrap(), &oids[3..]);
+        // good == bad leaves nothing to bisect
+        assert!(first_parent_range(&repo, oids[1], oids[1]).unwrap().is_empty());
+        // a descendant of bad is not an ancestor
+        assert!(first_parent_range(&repo, oids[3], oids[1]).is_err());
+        let _ = fs::remove_dir_all(repo.path().parent().unwrap());
+    }
+
+    #[test]
+    fn first_parent_range_is_bounded() {
+        let (repo, oids) = chain("first-parent-range-bound", MAX_BISECT_RANGE + 2);
+        let last = oids.len() - 1;
+        let range = first_parent_range(&repo, oids[1], oids[last]).unwrap();
+        assert_eq!(range.len(), MAX_BISECT_RANGE);
+        assert!(first_parent_range(&repo, oids[0], oids[last]).is_err());
+        let _ = fs::remove_dir_all(repo.path().parent().unwrap());
+    }
+}
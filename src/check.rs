@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use config::{parse_config, Config};
+use extract;
+
+/// Reports regexes whose capture groups won't yield a metric value. A regex needs a `value`
+/// named group, or else one group (the value) or two (the metric name and the value); further
+/// groups are ignored.
+fn check_regexs(cfg: &Config) -> usize {
+    let mut problems = 0;
+    for b in cfg.benchmarks.iter() {
+        for (i, r) in b.result_expr.iter().enumerate() {
+            let names = r.capture_names().filter_map(|n| n).collect::<Vec<_>>();
+            // captures_len() includes the implicit group for the whole match
+            let groups = r.captures_len() - 1;
+            let problem = if names.contains(&"value") {
+                None
+            } else if !names.is_empty() {
+                Some("has named capture groups, but no `value` group")
+            } else {
+                match groups {
+                    0 => Some("has no capture groups, so it can't yield a value"),
+                    1 | 2 => None,
+                    _ => Some("has more than two capture groups; only the first two are used"),
+                }
+            };
+            if let Some(p) = problem {
+                println!("[{}] regex {} (`{}`) {}", b.name, i, r, p);
+                problems += 1;
+            }
+        }
+    }
+    problems
+}
+
+/// Validates the `taster.toml` at `path` and, if `sample` is given, shows the metrics that each
+/// benchmark would extract from it. Returns whether the config is free of problems.
+pub fn check_config(
+    path: &Path,
+    sample: Option<&Path>,
+    def_imp_threshold: f64,
+    def_reg_threshold: f64,
+) -> bool {
+    let cfg = match parse_config(path, def_imp_threshold, def_reg_threshold) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}: {}", path.display(), e);
+            return false;
+        }
+    };
+    println!(
+        "{}: parsed {} benchmarks",
+        path.display(),
+        cfg.benchmarks.len()
+    );
+
+    let problems = check_regexs(&cfg);
+
+    if let Some(sample) = sample {
+        let mut buf = String::new();
+        if let Err(e) = File::open(sample).and_then(|mut f| f.read_to_string(&mut buf)) {
+            println!("failed to read sample output {}: {}", sample.display(), e);
+            return false;
+        }
+        for b in cfg.benchmarks.iter() {
+            let mut metrics = extract::metrics(b, buf.lines())
+                .into_iter()
+                .map(|(k, m)| (k, m.normalized()))
+                .collect::<Vec<_>>();
+            metrics.sort_by(|a, b| a.0.cmp(&b.0));
+            println!("[{}] extracts {} metrics:", b.name, metrics.len());
+            for (m, v) in metrics {
+                println!("  {}: {}", m, v);
+            }
+        }
+    }
+
+    if problems > 0 {
+        println!("found {} problems", problems);
+    }
+    problems == 0
+}
@@ -1,108 +1,364 @@
+use glob::Pattern;
 use regex::Regex;
-use std::collections::HashMap;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 use std::io::{Error, ErrorKind, Read};
-use std::path::Path;
-use toml;
+use std::path::{Component, Path};
+use toml::{self, Spanned};
+
+/// How a benchmark reports its results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Only through `regexs`.
+    Regex,
+    /// As libtest's `cargo bench` output, with one metric per bench function.
+    Libtest,
+    /// In the `estimates.json` files that Criterion.rs writes, with metrics for the mean and
+    /// median of each benchmark ID.
+    Criterion,
+    /// As records of Taster's JSON-lines metric protocol, on stdout or in the file named by
+    /// `TASTER_METRICS_FILE`.
+    JsonLines,
+}
+
+/// How metrics are extracted from a result file.
+#[derive(Clone, Debug)]
+pub enum ResultFormat {
+    /// Each row of a CSV file with a header yields the values in `columns`. If `name_column` is
+    /// given, the metrics are named after the row's value in it, too; otherwise, later rows
+    /// replace the values of earlier ones.
+    Csv {
+        columns: Vec<String>,
+        name_column: Option<String>,
+    },
+    /// The values at the given JSON pointers in a JSON file, by metric name.
+    Json { pointers: Vec<(String, String)> },
+}
+
+/// Files a benchmark writes its results to, which are read once it has exited.
+#[derive(Clone, Debug)]
+pub struct ResultFile {
+    /// Glob pattern relative to the workdir.
+    pub path: String,
+    pub format: ResultFormat,
+}
 
 #[derive(Clone, Debug)]
 pub struct Benchmark {
     pub name: String,
     pub cmd: String,
     pub args: Vec<String>,
+    pub format: OutputFormat,
     pub result_expr: Vec<Regex>,
+    pub result_files: Vec<ResultFile>,
     pub lower_is_better: bool,
     pub improvement_threshold: f64,
     pub regression_threshold: f64,
+    /// Number of measured runs whose samples are aggregated for each metric.
+    pub runs: usize,
+    /// Number of runs to discard before measuring.
+    pub warmup_runs: usize,
+    /// p-value below which a difference to the baseline counts as significant.
+    pub significance: f64,
+    /// Whether a change is only significant if the baseline lies outside the confidence interval
+    /// Criterion reported for the new result (when there are too few samples for a t-test).
+    pub criterion_ci: bool,
 }
 
 pub struct Config {
     pub benchmarks: Vec<Benchmark>,
     pub slack_aliases: HashMap<String, String>,
+    pub email_aliases: HashMap<String, String>,
     pub version: Option<i64>,
 }
 
-pub fn parse_config(
-    cfg: &Path,
-    def_imp_threshold: f64,
-    def_reg_threshold: f64,
-) -> Result<Config, Error> {
-    let mut f = try!(fs::File::open(cfg));
-    let mut buf = String::new();
-    try!(f.read_to_string(&mut buf));
-
-    let value = match toml::Parser::new(&buf).parse() {
-        None => {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "failed to parse taster config!",
-            ))
+/// The top-level table of a `taster.toml`: a few reserved keys, and a table for each benchmark.
+/// This is deserialized straight from the source, rather than via `toml::Value`, so that errors
+/// say where in the file they occurred.
+struct ConfigFile {
+    version: Option<i64>,
+    /// Github username (or git author email) <-> Slack username mappings
+    slack_aliases: HashMap<String, String>,
+    /// Github username <-> email address mappings
+    email_aliases: HashMap<String, String>,
+    benchmarks: BTreeMap<String, BenchmarkSpec>,
+}
+
+struct ConfigFileVisitor;
+
+impl<'de> Visitor<'de> for ConfigFileVisitor {
+    type Value = ConfigFile;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a table of benchmarks")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ConfigFile, A::Error> {
+        let mut file = ConfigFile {
+            version: None,
+            slack_aliases: HashMap::new(),
+            email_aliases: HashMap::new(),
+            benchmarks: BTreeMap::new(),
+        };
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => file.version = Some(map.next_value()?),
+                "slack-aliases" => file.slack_aliases = map.next_value()?,
+                "email-aliases" => file.email_aliases = map.next_value()?,
+                _ => {
+                    let spec = map.next_value()?;
+                    file.benchmarks.insert(key, spec);
+                }
+            }
         }
-        Some(v) => v,
-    };
+        Ok(file)
+    }
+}
 
-    let to_bench = |t: (&String, &toml::Value)| {
-        Benchmark {
-            name: t.0.clone(),
-            cmd: String::from(t.1.lookup("command").unwrap().as_str().unwrap()),
-            args: t.1.as_table().unwrap()["args"]
-                .as_slice()
-                .unwrap()
-                .iter()
-                .map(|a| String::from(a.as_str().unwrap()))
-                .collect(),
-            result_expr: t.1
-                .lookup("regexs")
-                .unwrap()
-                .as_slice()
-                .unwrap()
-                .iter()
-                .map(|r| Regex::new(r.as_str().unwrap()).unwrap())
-                .collect(),
-            lower_is_better: match t.1.lookup("lower_better") {
-                None => false,
-                Some(v) => v.as_bool().unwrap(),
-            },
-            improvement_threshold: match t.1.lookup("improvement_threshold") {
-                None => def_imp_threshold,
-                Some(ref it) => it.as_float().unwrap(),
-            },
-            regression_threshold: match t.1.lookup("regression_threshold") {
-                None => def_reg_threshold,
-                Some(ref rt) => rt.as_float().unwrap(),
-            },
+impl<'de> Deserialize<'de> for ConfigFile {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<ConfigFile, D::Error> {
+        d.deserialize_map(ConfigFileVisitor)
+    }
+}
+
+/// A benchmark table as written in `taster.toml`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a benchmark table")]
+struct BenchmarkSpec {
+    command: String,
+    args: Vec<String>,
+    format: Option<Spanned<String>>,
+    regexs: Option<Vec<Spanned<String>>>,
+    #[serde(default)]
+    result_files: Vec<ResultFileSpec>,
+    lower_better: Option<bool>,
+    improvement_threshold: Option<f64>,
+    regression_threshold: Option<f64>,
+    runs: Option<Spanned<usize>>,
+    #[serde(default)]
+    warmup_runs: usize,
+    #[serde(default = "default_significance")]
+    significance: f64,
+    #[serde(default = "default_criterion_ci")]
+    criterion_ci: bool,
+}
+
+/// A `[[<benchmark>.result_files]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResultFileSpec {
+    path: Spanned<String>,
+    format: String,
+    #[serde(default)]
+    columns: Vec<String>,
+    name_column: Option<String>,
+    #[serde(default)]
+    pointers: BTreeMap<String, String>,
+}
+
+/// Checks a `result_files` entry, given as `spec`, of the benchmark `name` in `src`.
+fn result_file(src: &str, name: &str, spec: ResultFileSpec) -> Result<ResultFile, Error> {
+    let location = at(src, name, &spec.path);
+    let path = spec.path.into_inner();
+    let err = |msg: &str| invalid(format!("{}: result file `{}` {}", location, path, msg));
+
+    Pattern::new(&path).map_err(|e| err(&format!("is not a valid glob: {}", e)))?;
+    // the files are archived and served with the logs, so they must come from the workdir
+    let escapes = Path::new(&path).components().any(|c| match c {
+        Component::Normal(_) | Component::CurDir => false,
+        _ => true,
+    });
+    if escapes {
+        return Err(err("must be relative to the workdir, without `..`"));
+    }
+
+    let format = match spec.format.as_str() {
+        "csv" => {
+            if spec.columns.is_empty() || !spec.pointers.is_empty() {
+                return Err(err("is a CSV file, so it needs `columns` (and no `pointers`)"));
+            }
+            ResultFormat::Csv {
+                columns: spec.columns,
+                name_column: spec.name_column,
+            }
+        }
+        "json" => {
+            if spec.pointers.is_empty() || !spec.columns.is_empty() || spec.name_column.is_some()
+            {
+                return Err(err(
+                    "is a JSON file, so it needs `pointers` (and no `columns` or `name_column`)",
+                ));
+            }
+            if let Some(p) = spec.pointers.values().find(|p| !p.is_empty() && !p.starts_with('/'))
+            {
+                return Err(err(&format!("has an invalid JSON pointer `{}`", p)));
+            }
+            ResultFormat::Json {
+                pointers: spec.pointers.into_iter().collect(),
+            }
+        }
+        f => {
+            return Err(err(&format!(
+                "has unknown format \"{}\"; expected \"csv\" or \"json\"",
+                f
+            )))
         }
     };
+    Ok(ResultFile {
+        path,
+        format,
+    })
+}
+
+fn default_significance() -> f64 {
+    0.05
+}
+
+fn default_criterion_ci() -> bool {
+    true
+}
 
-    // Github <-> Slack username mappings
-    let slack_aliases = value
-        .iter()
-        .filter(|t| t.0 == "slack-aliases")
-        .flat_map(|t| {
-            t.1
-                .as_table()
-                .unwrap()
-                .iter()
-                .map(|(k, v)| (k.clone(), String::from(v.as_str().unwrap())))
+/// Returns the (1-based) line and column of the byte offset `pos` in `src`.
+fn line_col(src: &str, pos: usize) -> (usize, usize) {
+    let before = &src[..pos];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Describes the location of `value` in the benchmark `name` of `src`.
+fn at<T>(src: &str, name: &str, value: &Spanned<T>) -> String {
+    let (line, col) = line_col(src, value.start());
+    format!("in benchmark [{}] at line {} column {}", name, line, col)
+}
+
+/// Returns the (1-based) line on which the table `[name]` starts in `src`, if we can find it.
+/// TOML doesn't give us the positions of tables, so this looks for the table's header instead.
+fn table_line(src: &str, name: &str) -> Option<usize> {
+    let plain = format!("[{}]", name);
+    let quoted = format!("[\"{}\"]", name);
+    src.lines()
+        .position(|l| {
+            let l = l.trim();
+            l.starts_with(&plain) || l.starts_with(&quoted)
         })
-        .collect::<HashMap<_, _>>();
+        .map(|i| i + 1)
+}
 
-    // Taster config version
-    let version = match value.get("version") {
-        None => None,
-        Some(v) => Some(v.as_integer().unwrap()),
-    };
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// Parses the contents of a `taster.toml`. All problems are reported as errors (rather than
+/// panics) that describe where in the file they are.
+fn parse_config_str(
+    src: &str,
+    def_imp_threshold: f64,
+    def_reg_threshold: f64,
+) -> Result<Config, Error> {
+    // syntax errors and ill-typed values are reported with their line and column, but toml only
+    // locates unknown or missing keys by the header of the table they're (not) in
+    let file: ConfigFile = toml::from_str(src).map_err(|e| invalid(format!("{}", e)))?;
 
     // Benchmark definitions
-    let benchmarks = value
-        .iter()
-        .filter(|t| t.0 != "slack-aliases" && t.0 != "version")
-        .map(|t| to_bench(t))
-        .collect();
+    let mut benchmarks = Vec::new();
+    for (name, spec) in file.benchmarks {
+        let result_expr = spec.regexs
+            .iter()
+            .flat_map(|rs| rs.iter())
+            .map(|r| {
+                Regex::new(r.get_ref()).map_err(|e| {
+                    invalid(format!(
+                        "{}: invalid regex `{}`: {}",
+                        at(src, &name, r),
+                        r.get_ref(),
+                        e
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let format = match spec.format {
+            None => OutputFormat::Regex,
+            Some(ref f) => match f.get_ref().as_str() {
+                "regex" => OutputFormat::Regex,
+                "libtest" => OutputFormat::Libtest,
+                "criterion" => OutputFormat::Criterion,
+                "json-lines" => OutputFormat::JsonLines,
+                other => {
+                    return Err(invalid(format!(
+                        "{}: unknown `format` \"{}\"; expected \"regex\", \"libtest\", \
+                         \"criterion\", or \"json-lines\"",
+                        at(src, &name, f),
+                        other
+                    )))
+                }
+            },
+        };
+        if format == OutputFormat::Regex && spec.regexs.is_none() && spec.result_files.is_empty()
+        {
+            let location = match table_line(src, &name) {
+                Some(l) => format!("in benchmark [{}] (line {})", name, l),
+                None => format!("in benchmark [{}]", name),
+            };
+            return Err(invalid(format!(
+                "{}: `regexs` is required unless a built-in `format` or `result_files` are used",
+                location
+            )));
+        }
+        let result_files = spec.result_files
+            .into_iter()
+            .map(|f| result_file(src, &name, f))
+            .collect::<Result<Vec<_>, _>>()?;
+        let runs = match spec.runs {
+            None => 1,
+            Some(ref r) if *r.get_ref() == 0 => {
+                return Err(invalid(format!("{}: `runs` must be at least 1", at(src, &name, r))))
+            }
+            Some(r) => r.into_inner(),
+        };
+
+        benchmarks.push(Benchmark {
+            name,
+            cmd: spec.command,
+            args: spec.args,
+            format,
+            result_expr,
+            result_files,
+            // libtest and Criterion report times per iteration
+            lower_is_better: spec.lower_better.unwrap_or(
+                format == OutputFormat::Libtest || format == OutputFormat::Criterion,
+            ),
+            improvement_threshold: spec.improvement_threshold.unwrap_or(def_imp_threshold),
+            regression_threshold: spec.regression_threshold.unwrap_or(def_reg_threshold),
+            runs,
+            warmup_runs: spec.warmup_runs,
+            significance: spec.significance,
+            criterion_ci: spec.criterion_ci,
+        });
+    }
 
     Ok(Config {
-        benchmarks: benchmarks,
-        slack_aliases: slack_aliases,
-        version: version,
+        benchmarks,
+        slack_aliases: file.slack_aliases,
+        email_aliases: file.email_aliases,
+        version: file.version,
     })
 }
+
+/// Reads and parses the `taster.toml` at `cfg`. A missing file yields an error of kind
+/// `NotFound`, while an invalid one yields `InvalidInput` with a description of the problem.
+pub fn parse_config(
+    cfg: &Path,
+    def_imp_threshold: f64,
+    def_reg_threshold: f64,
+) -> Result<Config, Error> {
+    let mut f = fs::File::open(cfg)?;
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+
+    parse_config_str(&buf, def_imp_threshold, def_reg_threshold)
+}
@@ -1,48 +1,315 @@
-//use lettre::email::EmailBuilder;
-//use lettre::transport::smtp::SmtpTransportBuilder;
-//use lettre::transport::EmailTransport;
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
 
+use Commit;
 use Push;
 use config::Config;
-use taste::TastingResult;
+use notifier::Notifier;
+use stats::Summary;
+use taste::{BenchmarkResult, TastingResult};
+
+/// Connection settings for the SMTP server that notifications are sent through.
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    /// `(username, password)`
+    pub credentials: Option<(String, String)>,
+    /// Require STARTTLS.
+    pub tls: bool,
+    pub from: String,
+}
 
 pub struct EmailNotifier {
-    // mailer: SmtpTransport,
     addr: String,
     github_repo: String,
+    smtp: SmtpConfig,
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn status(ok: bool) -> &'static str {
+    if ok {
+        "passed"
+    } else {
+        "FAILED"
+    }
 }
 
 impl EmailNotifier {
-    pub fn new(addr: &str, repo_url: &str) -> EmailNotifier {
+    pub fn new(addr: &str, repo_url: &str, smtp: SmtpConfig) -> EmailNotifier {
         EmailNotifier {
-            // mailer: SmtpTransportBuilder::localhost().unwrap().build(),
             addr: String::from(addr),
             github_repo: String::from(repo_url),
+            smtp: smtp,
+        }
+    }
+
+    /// Returns the `(metric, value, percentage change, classification)` rows for `res`, sorted
+    /// by metric name.
+    fn metric_rows(res: &TastingResult) -> Vec<(String, String, String, &'static str)> {
+        let mut rows = Vec::new();
+        if let Some(ref r) = res.results {
+            for &(ref bm, ref status, ref metrics) in r {
+                if !status.success() {
+                    rows.push((bm.name.clone(), String::from("-"), String::from("-"), "failed"));
+                    continue;
+                }
+                for (k, v) in metrics {
+                    let (val, change, class): (&Summary, f64, &'static str) = match *v {
+                        BenchmarkResult::Improvement(ref s, p) => (s, p, "improvement"),
+                        BenchmarkResult::Neutral(ref s, p) => (s, p, "neutral"),
+                        BenchmarkResult::Regression(ref s, p) => (s, p, "regression"),
+                    };
+                    rows.push((
+                        k.clone(),
+                        format!("{}", val),
+                        format!("{:+.2}%", change * 100.0),
+                        class,
+                    ));
+                }
+            }
         }
+        rows.sort();
+        rows
+    }
+
+    fn text_body(&self, res: &TastingResult, commit: &Commit) -> String {
+        let mut body = format!(
+            "Taster results for {} ({})\n\n{}\n\nBuild: {}\nTests: {}\nBenchmarks: {}\n",
+            commit.id,
+            self.github_repo,
+            commit.msg,
+            status(res.build.success()),
+            status(res.test.success()),
+            status(res.bench)
+        );
+        if let Some(ref e) = res.config_error {
+            body.push_str(&format!("\nInvalid taster.toml: {}\n", e));
+        }
+        let rows = EmailNotifier::metric_rows(res);
+        if !rows.is_empty() {
+            body.push_str("\n");
+            for (metric, val, change, class) in rows {
+                body.push_str(&format!("{}: {} ({}, {})\n", metric, val, change, class));
+            }
+        }
+        body.push_str(&format!("\n{}\n", commit.url));
+        body
+    }
+
+    fn html_body(&self, res: &TastingResult, commit: &Commit) -> String {
+        let mut body = format!(
+            "<p>Taster results for <a href=\"{}\">{}</a> ({})</p>\n<pre>{}</pre>\n\
+             <ul>\n<li>Build: {}</li>\n<li>Tests: {}</li>\n<li>Benchmarks: {}</li>\n</ul>\n",
+            escape_html(&commit.url),
+            commit.id,
+            escape_html(&self.github_repo),
+            escape_html(&commit.msg),
+            status(res.build.success()),
+            status(res.test.success()),
+            status(res.bench)
+        );
+        if let Some(ref e) = res.config_error {
+            body.push_str(&format!(
+                "<p>Invalid taster.toml: <code>{}</code></p>\n",
+                escape_html(e)
+            ));
+        }
+        let rows = EmailNotifier::metric_rows(res);
+        if !rows.is_empty() {
+            body.push_str(
+                "<table>\n<tr><th>Metric</th><th>Value</th><th>Change</th><th></th></tr>\n",
+            );
+            for (metric, val, change, class) in rows {
+                let color = match class {
+                    "improvement" => "green",
+                    "regression" | "failed" => "red",
+                    _ => "black",
+                };
+                body.push_str(&format!(
+                    "<tr style=\"color: {}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    color,
+                    escape_html(&metric),
+                    escape_html(&val),
+                    change,
+                    class
+                ));
+            }
+            body.push_str("</table>\n");
+        }
+        body
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        "email"
     }
 
-    pub fn notify(
+    fn notify(
         &self,
-        _cfg: Option<&Config>,
-        _res: &TastingResult,
+        cfg: Option<&Config>,
+        res: &TastingResult,
         push: &Push,
+        commit: &Commit,
     ) -> Result<(), String> {
-        // Unfortunately, we have to construct the transport here, since hyper forces us to accept
-        // &self rather than &mut self, so we can't store the mailer in the `EmailNotifier` struct
-        /*let mut mailer = SmtpTransportBuilder::localhost().unwrap().build();
+        let taste = if !res.build.success() || !res.bench {
+            "was inedible"
+        } else if !res.test.success() {
+            "had a mixed palate"
+        } else {
+            "tasted nice"
+        };
 
-        let email = EmailBuilder::new()
+        let mut builder = EmailBuilder::new()
             .to(self.addr.as_str())
-            .from("taster@tbilisi.csail.mit.edu")
-            .body(&format!("Hello world from {}", self.github_repo))
-            .subject(&format!("[taster] Result for {}", push.head_commit.id))
+            .from(self.smtp.from.as_str())
+            .subject(format!(
+                "[taster] {} {}",
+                &format!("{}", commit.id)[0..6],
+                taste
+            ))
+            .alternative(self.html_body(res, commit), self.text_body(res, commit));
+        // also notify the pusher, if we know their email address
+        if let (Some(cfg), Some(p)) = (cfg, push.pusher.as_ref()) {
+            if let Some(a) = cfg.email_aliases.get(p) {
+                builder = builder.cc(a.as_str());
+            }
+        }
+        let email = builder
             .build()
-            .unwrap();
+            .map_err(|e| format!("failed to build email: {:?}", e))?;
+
+        // Unfortunately, we have to construct the transport here, since hyper forces us to accept
+        // &self rather than &mut self, so we can't store the mailer in the `EmailNotifier` struct
+        let security = if self.smtp.tls {
+            let connector = TlsConnector::new().map_err(|e| format!("{:?}", e))?;
+            ClientSecurity::Required(ClientTlsParameters::new(self.smtp.host.clone(), connector))
+        } else {
+            ClientSecurity::None
+        };
+        let mut client = SmtpClient::new((self.smtp.host.as_str(), self.smtp.port), security)
+            .map_err(|e| format!("{:?}", e))?;
+        if let Some((ref user, ref password)) = self.smtp.credentials {
+            client = client.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+        let mut mailer = client.transport();
 
-        match mailer.send(email) {
+        match mailer.send(email.into()) {
             Ok(_) => Ok(()),
             Err(e) => Err(format!("{:?}", e)),
-        }*/
-        Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Benchmark, OutputFormat};
+    use git2::Oid;
+    use process::Outcome;
+    use std::collections::HashMap;
+
+    fn bench(name: &str) -> Benchmark {
+        Benchmark {
+            name: String::from(name),
+            cmd: String::from("true"),
+            args: vec![],
+            format: OutputFormat::Regex,
+            result_expr: vec![],
+            result_files: vec![],
+            lower_is_better: true,
+            improvement_threshold: 0.05,
+            regression_threshold: 0.05,
+            runs: 1,
+            warmup_runs: 0,
+            significance: 0.05,
+            criterion_ci: false,
+        }
+    }
+
+    fn tasting() -> (EmailNotifier, TastingResult) {
+        let smtp = SmtpConfig {
+            host: String::from("localhost"),
+            port: 25,
+            credentials: None,
+            tls: false,
+            from: String::from("taster@example.com"),
+        };
+        let notifier = EmailNotifier::new("dev@example.com", "https://github.com/o/r", smtp);
+        let mut metrics = HashMap::new();
+        metrics.insert(
+            String::from("a <b>"),
+            BenchmarkResult::Regression(Summary::new(vec![2.0]), 0.25),
+        );
+        metrics.insert(
+            String::from("speed"),
+            BenchmarkResult::Improvement(Summary::new(vec![1.0]), -0.1),
+        );
+        let id = Oid::from_str("0123456789abcdef0123456789abcdef01234567").unwrap();
+        let res = TastingResult {
+            branch: Some(String::from("master")),
+            commit: Commit {
+                id: id,
+                msg: String::from("fix <things> & stuff"),
+                url: format!("https://github.com/o/r/commit/{}", id),
+            },
+            build: Outcome::Success,
+            test: Outcome::Failed(101),
+            bench: false,
+            results: Some(vec![
+                (bench("ok"), Outcome::Success, metrics),
+                (bench("broken"), Outcome::TimedOut, HashMap::new()),
+            ]),
+            baselines: HashMap::new(),
+            config_error: None,
+        };
+        (notifier, res)
+    }
+
+    #[test]
+    fn text_body() {
+        let (notifier, res) = tasting();
+        assert_eq!(
+            notifier.text_body(&res, &res.commit),
+            "Taster results for 0123456789abcdef0123456789abcdef01234567 \
+             (https://github.com/o/r)\n\n\
+             fix <things> & stuff\n\n\
+             Build: passed\nTests: FAILED\nBenchmarks: FAILED\n\n\
+             a <b>: 2.00 (+25.00%, regression)\n\
+             broken: - (-, failed)\n\
+             speed: 1.00 (-10.00%, improvement)\n\n\
+             https://github.com/o/r/commit/0123456789abcdef0123456789abcdef01234567\n"
+        );
+    }
+
+    #[test]
+    fn html_body_is_escaped() {
+        let (notifier, mut res) = tasting();
+        res.config_error = Some(String::from("expected <table>"));
+        let body = notifier.html_body(&res, &res.commit);
+        assert!(!body.contains("<things>") && !body.contains("<b>"));
+        assert!(body.contains("<pre>fix &lt;things&gt; &amp; stuff</pre>"));
+        assert!(body.contains("<li>Tests: FAILED</li>"));
+        assert!(body.contains("<p>Invalid taster.toml: <code>expected &lt;table&gt;</code></p>"));
+        assert!(body.contains(
+            "<tr style=\"color: red\"><td>a &lt;b&gt;</td><td>2.00</td><td>+25.00%</td>\
+             <td>regression</td></tr>"
+        ));
+        assert!(body.contains(
+            "<tr style=\"color: red\"><td>broken</td><td>-</td><td>-</td><td>failed</td></tr>"
+        ));
+        assert!(body.contains(
+            "<tr style=\"color: green\"><td>speed</td><td>1.00</td><td>-10.00%</td>\
+             <td>improvement</td></tr>"
+        ));
     }
 }
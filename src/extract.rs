@@ -0,0 +1,904 @@
+use glob::{self, Pattern};
+use regex::Regex;
+use serde_json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use config::{Benchmark, OutputFormat, ResultFormat};
+
+/// A value reported for a metric by a single benchmark run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    pub value: f64,
+    pub unit: Option<String>,
+    /// Spread around `value` that the benchmark itself reported, if any.
+    pub deviation: Option<f64>,
+    /// Confidence interval for `value` that the benchmark itself reported, if any.
+    pub ci: Option<(f64, f64)>,
+    /// Overrides the benchmark's `lower_better` for this metric.
+    pub lower_is_better: Option<bool>,
+}
+
+impl Measurement {
+    fn new(value: f64) -> Measurement {
+        Measurement {
+            value,
+            unit: None,
+            deviation: None,
+            ci: None,
+            lower_is_better: None,
+        }
+    }
+
+    /// Converts the measurement to its normalized unit (see `normalize_unit`), so that it can be
+    /// compared to measurements reported in other units.
+    pub fn normalized(self) -> Measurement {
+        let (factor, unit) = match self.unit {
+            Some(ref u) => normalize_unit(u),
+            None => return self,
+        };
+        Measurement {
+            value: self.value * factor,
+            unit: if unit.is_empty() { None } else { Some(unit) },
+            deviation: self.deviation.map(|d| d * factor),
+            ci: self.ci.map(|(lo, hi)| (lo * factor, hi * factor)),
+            lower_is_better: self.lower_is_better,
+        }
+    }
+}
+
+/// Time units, by how many nanoseconds they are.
+const TIME_UNITS: &'static [(&'static str, f64)] = &[
+    ("ns", 1.0),
+    ("us", 1e3),
+    ("µs", 1e3),
+    ("μs", 1e3),
+    ("ms", 1e6),
+    ("s", 1e9),
+    ("sec", 1e9),
+];
+
+/// Magnitude prefixes (and suffixes of numbers), by their factor.
+const MAGNITUDES: &'static [(&'static str, f64)] = &[
+    ("Ki", 1024.0),
+    ("Mi", 1024.0 * 1024.0),
+    ("Gi", 1024.0 * 1024.0 * 1024.0),
+    ("K", 1e3),
+    ("k", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+];
+
+/// Units that K/M/G prefixes are dropped from; we leave other units that happen to start with
+/// one of those letters alone.
+const PREFIXABLE_UNITS: &'static [&'static str] = &[
+    "", "B", "b", "bit", "bits", "bps", "Bps", "op", "ops", "req", "reqs", "iter", "iters",
+    "elem", "elems", "msg", "msgs", "Hz", "flops", "FLOPS",
+];
+
+/// Returns the factor by which values in `unit` must be multiplied to normalize them, and the
+/// unit they are then in: times are converted to nanoseconds, and K/M/G (or Ki/Mi/Gi) prefixes
+/// are dropped (from a known set of units). Anything from a `/` on (as in `ms/iter` or `MB/s`)
+/// is kept.
+pub fn normalize_unit(unit: &str) -> (f64, String) {
+    let unit = unit.trim();
+    let (head, tail) = match unit.find('/') {
+        Some(i) => (&unit[..i], &unit[i..]),
+        None => (unit, ""),
+    };
+    if let Some(&(_, f)) = TIME_UNITS.iter().find(|&&(u, _)| u == head) {
+        return (f, format!("ns{}", tail));
+    }
+    for &(prefix, f) in MAGNITUDES {
+        if head.starts_with(prefix) && PREFIXABLE_UNITS.iter().any(|u| *u == &head[prefix.len()..])
+        {
+            return (f, format!("{}{}", &head[prefix.len()..], tail));
+        }
+    }
+    (1.0, String::from(unit))
+}
+
+/// Picks a readable unit to show `value`, which is in the normalized `unit`, in: nanoseconds are
+/// shown in the largest time unit that the value is at least one of. Returns the factor to
+/// divide `value` by, and the unit.
+pub fn display_unit(value: f64, unit: &str) -> (f64, String) {
+    if unit == "ns" || unit.starts_with("ns/") {
+        for &(u, f) in &[("s", 1e9), ("ms", 1e6), ("µs", 1e3)] {
+            if value.abs() >= f {
+                return (f, format!("{}{}", u, &unit[2..]));
+            }
+        }
+    }
+    (1.0, String::from(unit))
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        if let Some(d) = self.deviation {
+            write!(f, " ± {}", d)?;
+        }
+        if let Some((lo, hi)) = self.ci {
+            write!(f, " [{}, {}]", lo, hi)?;
+        }
+        if let Some(ref u) = self.unit {
+            write!(f, " {}", u)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a number as benchmarks print it, ignoring thousands separators (`1,234` or `1_234`)
+/// and applying K/M/G suffixes (`1.5K`). NaN and infinities are rejected.
+pub fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim()
+        .chars()
+        .filter(|c| *c != ',' && *c != '_')
+        .collect::<String>();
+    let (num, factor) = MAGNITUDES
+        .iter()
+        .find(|&&(m, _)| s.ends_with(m))
+        .map_or((&s[..], 1.0), |&(m, f)| (&s[..s.len() - m.len()], f));
+    num.parse::<f64>()
+        .ok()
+        .map(|v| v * factor)
+        .filter(|v| v.is_finite())
+}
+
+/// Matches the result lines `cargo bench` prints for libtest benchmarks, e.g.
+/// `test foo::bar ... bench:       1,234 ns/iter (+/- 56)`.
+const LIBTEST_EXPR: &'static str =
+    r"^test (\S+)\s+\.\.\. bench:\s+([0-9][0-9,._]*) (\S+) \(\+/- ([0-9][0-9,._]*)\)";
+
+/// Extracts the metrics that libtest reports in `line`, if any: one per bench function, named
+/// after it.
+fn libtest(expr: &Regex, line: &str) -> Option<(String, Measurement)> {
+    let cap = expr.captures(line.trim())?;
+    Some((
+        String::from(cap.at(1)?),
+        Measurement {
+            value: parse_number(cap.at(2)?)?,
+            unit: cap.at(3).map(String::from),
+            deviation: cap.at(4).and_then(parse_number),
+            ci: None,
+            lower_is_better: None,
+        },
+    ))
+}
+
+/// Applies `bench`'s regexes to `line`, adding the values of all metrics found to `res`. A regex
+/// with a `value` named capture group may name the metric and its unit with `metric` and `unit`
+/// groups. Otherwise, for each regex with two capture groups, the first one names the metric.
+/// Metrics without a name are named after the regex's index.
+fn regexs(bench: &Benchmark, line: &str, res: &mut HashMap<String, Measurement>) {
+    for (i, regex) in bench.result_expr.iter().enumerate() {
+        let named = regex.capture_names().any(|n| n == Some("value"));
+        for cap in regex.captures_iter(line) {
+            let (metric, value, unit) = if named {
+                (
+                    cap.name("metric").map_or(format!("{}", i), String::from),
+                    cap.name("value"),
+                    cap.name("unit").map(String::from),
+                )
+            } else if cap.len() > 2 {
+                (String::from(cap.at(1).unwrap()), cap.at(2), None)
+            } else {
+                (format!("{}", i), cap.at(1), None)
+            };
+            let bm_name = format!("{}/{}", bench.name, &metric);
+            if let Some(c) = value {
+                match parse_number(c) {
+                    Some(val) => {
+                        let mut m = Measurement::new(val);
+                        m.unit = unit.filter(|u| !u.trim().is_empty());
+                        res.insert(bm_name, m);
+                    }
+                    None => println!(
+                        "failed to parse value '{}' for {} into f64 number, ignoring",
+                        c, bm_name
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Extracts the metrics in a benchmark run's output `lines`, according to the benchmark's
+/// output format. Regexes, if given, are applied in addition to the built-in parsers. Metric
+/// names are prefixed with the benchmark's name.
+pub fn metrics<'a, I>(bench: &Benchmark, lines: I) -> HashMap<String, Measurement>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let libtest_expr = match bench.format {
+        OutputFormat::Libtest => Some(Regex::new(LIBTEST_EXPR).unwrap()),
+        _ => None,
+    };
+
+    let mut res = HashMap::new();
+    for l in lines {
+        let builtin = match libtest_expr {
+            Some(ref expr) => libtest(expr, l),
+            None if bench.format == OutputFormat::JsonLines => {
+                // other JSON the benchmark prints is none of our business
+                serde_json::from_str::<MetricRecord>(l.trim())
+                    .ok()
+                    .map(MetricRecord::measurement)
+            }
+            None => None,
+        };
+        if let Some((metric, m)) = builtin {
+            res.insert(format!("{}/{}", bench.name, metric), m);
+        }
+        regexs(bench, l, &mut res);
+    }
+    res
+}
+
+/// Environment variable that names the file to which a benchmark using the JSON-lines protocol
+/// may write its records, instead of printing them.
+pub const METRICS_FILE_VAR: &'static str = "TASTER_METRICS_FILE";
+
+/// A record of the JSON-lines metric protocol, e.g.
+/// `{"metric": "latency", "value": 1.5, "unit": "ms", "lower_is_better": true}`.
+#[derive(Deserialize)]
+struct MetricRecord {
+    metric: String,
+    value: f64,
+    unit: Option<String>,
+    lower_is_better: Option<bool>,
+}
+
+impl MetricRecord {
+    fn measurement(self) -> (String, Measurement) {
+        (
+            self.metric,
+            Measurement {
+                value: self.value,
+                unit: self.unit,
+                deviation: None,
+                ci: None,
+                lower_is_better: self.lower_is_better,
+            },
+        )
+    }
+}
+
+/// A file to which a single benchmark run may write JSON-lines metric records. It is removed when
+/// dropped.
+pub struct MetricsFile {
+    pub path: PathBuf,
+}
+
+impl MetricsFile {
+    /// Creates an empty metrics file at `path`. This fails if anything is already there, so that
+    /// we never read records from (or have the benchmark write through) a file or symlink that
+    /// someone else put in place.
+    pub fn create(path: PathBuf) -> io::Result<MetricsFile> {
+        OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok(MetricsFile { path })
+    }
+
+    /// Reads the records that were written to the file (if the benchmark didn't remove it).
+    /// Lines that aren't valid records are reported and skipped.
+    pub fn read(&self, bench: &Benchmark) -> HashMap<String, Measurement> {
+        let mut res = HashMap::new();
+        let mut buf = String::new();
+        match File::open(&self.path).and_then(|mut f| f.read_to_string(&mut buf)) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return res,
+            Err(e) => {
+                println!("failed to read metrics of {}: {}", bench.name, e);
+                return res;
+            }
+        }
+        for (i, l) in buf.lines().enumerate().filter(|&(_, l)| !l.trim().is_empty()) {
+            match serde_json::from_str::<MetricRecord>(l) {
+                Ok(r) => {
+                    let (metric, m) = r.measurement();
+                    res.insert(format!("{}/{}", bench.name, metric), m);
+                }
+                Err(e) => println!(
+                    "invalid metric record on line {} of {}'s metrics file, ignoring: {}",
+                    i + 1,
+                    bench.name,
+                    e
+                ),
+            }
+        }
+        res
+    }
+}
+
+impl Drop for MetricsFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Deserialize)]
+struct CriterionInterval {
+    lower_bound: f64,
+    upper_bound: f64,
+}
+
+#[derive(Deserialize)]
+struct CriterionEstimate {
+    confidence_interval: CriterionInterval,
+    point_estimate: f64,
+}
+
+/// The parts of Criterion's `estimates.json` we use; Criterion before 0.3 capitalized the keys.
+#[derive(Deserialize)]
+struct CriterionEstimates {
+    #[serde(alias = "Mean")]
+    mean: CriterionEstimate,
+    #[serde(alias = "Median")]
+    median: CriterionEstimate,
+}
+
+/// Criterion's `benchmark.json`, which names the benchmark a directory belongs to.
+#[derive(Deserialize)]
+struct CriterionBenchmark {
+    full_id: String,
+}
+
+/// Collects the `new/estimates.json` files below `dir` that were written at or after `since`.
+fn find_estimates(dir: &Path, since: SystemTime, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let estimates = path.join("new").join("estimates.json");
+        if fs::metadata(&estimates).and_then(|m| m.modified()).ok() >= Some(since) {
+            found.push(estimates);
+        }
+        find_estimates(&path, since, found)?;
+    }
+    Ok(())
+}
+
+/// Reads the results that Criterion wrote below `target/criterion` in `workdir` (or in
+/// `$CARGO_TARGET_DIR`) since `since`, i.e., during the benchmark's run; older results, e.g. from
+/// an earlier tasting in the same worktree, are ignored. Yields the mean and median of each
+/// benchmark ID, in nanoseconds, along with Criterion's confidence intervals for them.
+pub fn criterion(
+    bench: &Benchmark,
+    workdir: &Path,
+    since: SystemTime,
+) -> HashMap<String, Measurement> {
+    let target = match env::var_os("CARGO_TARGET_DIR") {
+        Some(t) => workdir.join(t),
+        None => workdir.join("target"),
+    };
+    let root = target.join("criterion");
+
+    let mut files = Vec::new();
+    if let Err(e) = find_estimates(&root, since, &mut files) {
+        println!("failed to find Criterion results in {}: {}", root.display(), e);
+    }
+
+    let mut res = HashMap::new();
+    for f in files {
+        let dir = f.parent().unwrap();
+        let estimates: CriterionEstimates = match File::open(&f)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+        {
+            Ok(e) => e,
+            Err(e) => {
+                println!("failed to read {}: {}, ignoring", f.display(), e);
+                continue;
+            }
+        };
+        // the directory names are sanitized, so prefer the ID Criterion recorded
+        let id = File::open(dir.join("benchmark.json"))
+            .ok()
+            .and_then(|f| serde_json::from_reader::<_, CriterionBenchmark>(f).ok())
+            .map(|b| b.full_id)
+            .unwrap_or_else(|| {
+                let rel = dir.parent().unwrap().strip_prefix(&root).unwrap();
+                rel.to_string_lossy().into_owned()
+            });
+
+        for &(stat, e) in &[("mean", &estimates.mean), ("median", &estimates.median)] {
+            let ci = &e.confidence_interval;
+            res.insert(
+                format!("{}/{}/{}", bench.name, id, stat),
+                Measurement {
+                    value: e.point_estimate,
+                    unit: Some(String::from("ns")),
+                    deviation: None,
+                    ci: Some((ci.lower_bound, ci.upper_bound)),
+                    lower_is_better: None,
+                },
+            );
+        }
+    }
+    res
+}
+
+/// Splits a line of a CSV file into its fields. Fields may be quoted, with `""` standing for a
+/// quote inside them, but may not span several lines.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Extracts the values in `columns` of each row of the CSV file `src` (whose first line is its
+/// header), naming the metrics after the value in `name_column`, if given, and the column.
+fn csv_metrics(
+    bench: &Benchmark,
+    src: &str,
+    columns: &[String],
+    name_column: Option<&String>,
+    res: &mut HashMap<String, Measurement>,
+) -> Result<(), String> {
+    let mut lines = src.lines().filter(|l| !l.trim().is_empty());
+    let header = match lines.next() {
+        Some(h) => csv_fields(h),
+        None => return Ok(()),
+    };
+    let index = |col: &str| {
+        header
+            .iter()
+            .position(|h| h.trim() == col)
+            .ok_or_else(|| format!("there is no column `{}`", col))
+    };
+    let value_cols = columns
+        .iter()
+        .map(|c| index(c).map(|i| (c, i)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let name_col = match name_column {
+        Some(c) => Some(index(c)?),
+        None => None,
+    };
+
+    for (row, line) in lines.enumerate() {
+        let fields = csv_fields(line);
+        for &(col, i) in value_cols.iter() {
+            let metric = match name_col {
+                Some(n) => {
+                    let name = fields.get(n).map_or("", |f| f.trim());
+                    format!("{}/{}/{}", bench.name, name, col)
+                }
+                None => format!("{}/{}", bench.name, col),
+            };
+            match fields.get(i).and_then(|f| parse_number(f)) {
+                Some(v) => {
+                    res.insert(metric, Measurement::new(v));
+                }
+                None => println!(
+                    "row {} has no numeric value in column `{}` for {}, ignoring",
+                    row + 1,
+                    col,
+                    metric
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extracts the values at `pointers` in the JSON document `src`, by metric name. Numbers given
+/// as strings are accepted too.
+fn json_metrics(
+    bench: &Benchmark,
+    src: &str,
+    pointers: &[(String, String)],
+    res: &mut HashMap<String, Measurement>,
+) -> Result<(), String> {
+    let doc = serde_json::from_str::<serde_json::Value>(src).map_err(|e| e.to_string())?;
+    for &(ref name, ref pointer) in pointers {
+        let metric = format!("{}/{}", bench.name, name);
+        let value = doc.pointer(pointer)
+            .and_then(|v| v.as_f64().or_else(|| v.as_str().and_then(parse_number)));
+        match value {
+            Some(v) => {
+                res.insert(metric, Measurement::new(v));
+            }
+            None => println!("no number at `{}` for {}, ignoring", pointer, metric),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the metrics from the result files of `bench` that were written in `workdir` since
+/// `since`, i.e., by the benchmark's run. Returns them along with the files they were read from,
+/// which exclude any that (e.g., through a symlink) lie outside of the workdir.
+pub fn result_files(
+    bench: &Benchmark,
+    workdir: &Path,
+    since: SystemTime,
+) -> (HashMap<String, Measurement>, Vec<PathBuf>) {
+    let mut res = HashMap::new();
+    let mut read = Vec::new();
+    let root = match workdir.canonicalize() {
+        Ok(r) => r,
+        Err(e) => {
+            println!("failed to resolve {}: {}", workdir.display(), e);
+            return (res, read);
+        }
+    };
+
+    for rf in bench.result_files.iter() {
+        let pattern = format!("{}/{}", Pattern::escape(&root.to_string_lossy()), rf.path);
+        let paths = match glob::glob(&pattern) {
+            Ok(p) => p.filter_map(|p| p.ok()).collect::<Vec<_>>(),
+            Err(e) => {
+                println!("invalid result file pattern `{}`: {}", rf.path, e);
+                continue;
+            }
+        };
+        for path in paths {
+            let fresh = fs::metadata(&path).and_then(|m| m.modified()).ok() >= Some(since);
+            let inside = path.canonicalize().map(|p| p.starts_with(&root)).unwrap_or(false);
+            if !path.is_file() || !fresh || !inside || read.contains(&path) {
+                continue;
+            }
+
+            let mut src = String::new();
+            let parsed = File::open(&path)
+                .and_then(|mut f| f.read_to_string(&mut src))
+                .map_err(|e| e.to_string())
+                .and_then(|_| match rf.format {
+                    ResultFormat::Csv {
+                        ref columns,
+                        ref name_column,
+                    } => csv_metrics(bench, &src, columns, name_column.as_ref(), &mut res),
+                    ResultFormat::Json { ref pointers } => {
+                        json_metrics(bench, &src, pointers, &mut res)
+                    }
+                });
+            if let Err(e) = parsed {
+                println!("failed to read metrics from {}: {}", path.display(), e);
+            }
+            read.push(path);
+        }
+    }
+    (res, read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ResultFile;
+    use std::os::unix::fs::symlink;
+    use std::process;
+    use std::time::Duration;
+
+    fn bench(format: OutputFormat, regexs: &[&str]) -> Benchmark {
+        Benchmark {
+            name: String::from("bench"),
+            cmd: String::from("bench"),
+            args: vec![],
+            format,
+            result_expr: regexs.iter().map(|r| Regex::new(r).unwrap()).collect(),
+            result_files: vec![],
+            lower_is_better: true,
+            improvement_threshold: 0.1,
+            regression_threshold: 0.1,
+            runs: 1,
+            warmup_runs: 0,
+            significance: 0.05,
+            criterion_ci: true,
+        }
+    }
+
+    #[test]
+    fn libtest_lines() {
+        let out = "\
+running 3 tests
+test parse::tests::it_works ... ignored
+test bench_parse        ... bench:       1,234 ns/iter (+/- 56)
+test tests::bench_large ... bench:   1,234,567 ns/iter (+/- 12,345)
+test bench_copy         ... bench:         112 ns/iter (+/- 3) = 9142 MB/s
+test bench_precise      ... bench:       1,234.56 ns/iter (+/- 7.89)
+
+test result: ok. 0 passed; 0 failed; 1 ignored; 3 measured; 0 filtered out";
+        let res = metrics(&bench(OutputFormat::Libtest, &[]), out.lines());
+        assert_eq!(res.len(), 4);
+
+        let m = &res["bench/bench_parse"];
+        assert_eq!(m.value, 1234.0);
+        assert_eq!(m.unit, Some(String::from("ns/iter")));
+        assert_eq!(m.deviation, Some(56.0));
+        assert_eq!(res["bench/tests::bench_large"].value, 1234567.0);
+        assert_eq!(res["bench/tests::bench_large"].deviation, Some(12345.0));
+        assert_eq!(res["bench/bench_copy"].value, 112.0);
+        assert_eq!(res["bench/bench_precise"].value, 1234.56);
+        assert_eq!(res["bench/bench_precise"].deviation, Some(7.89));
+    }
+
+    #[test]
+    fn libtest_ignores_other_formats() {
+        let line = "test bench_parse ... bench:       1,234 ns/iter (+/- 56)";
+        assert!(metrics(&bench(OutputFormat::Regex, &[]), Some(line).into_iter()).is_empty());
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Some(42.0));
+        assert_eq!(parse_number(" 1.5 "), Some(1.5));
+        assert_eq!(parse_number("1,234,567"), Some(1234567.0));
+        assert_eq!(parse_number("1_234.5"), Some(1234.5));
+        assert_eq!(parse_number("1.5K"), Some(1500.0));
+        assert_eq!(parse_number("2k"), Some(2000.0));
+        assert_eq!(parse_number("3M"), Some(3e6));
+        assert_eq!(parse_number("1G"), Some(1e9));
+        assert_eq!(parse_number("2Ki"), Some(2048.0));
+        assert_eq!(parse_number("1Mi"), Some(1024.0 * 1024.0));
+        assert_eq!(parse_number("-0.25"), Some(-0.25));
+        assert_eq!(parse_number(""), None);
+        assert_eq!(parse_number("fast"), None);
+        assert_eq!(parse_number("12 ms"), None);
+        assert_eq!(parse_number("NaN"), None);
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("-inf"), None);
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(normalize_unit("ns"), (1.0, String::from("ns")));
+        assert_eq!(normalize_unit("us"), (1e3, String::from("ns")));
+        assert_eq!(normalize_unit("µs"), (1e3, String::from("ns")));
+        assert_eq!(normalize_unit("ms/iter"), (1e6, String::from("ns/iter")));
+        assert_eq!(normalize_unit("s"), (1e9, String::from("ns")));
+        assert_eq!(normalize_unit(" sec "), (1e9, String::from("ns")));
+        assert_eq!(normalize_unit("ns/iter"), (1.0, String::from("ns/iter")));
+        assert_eq!(normalize_unit("MB/s"), (1e6, String::from("B/s")));
+        assert_eq!(normalize_unit("KiB"), (1024.0, String::from("B")));
+        assert_eq!(normalize_unit("Gbps"), (1e9, String::from("bps")));
+        assert_eq!(normalize_unit("kops"), (1e3, String::from("ops")));
+        assert_eq!(normalize_unit("M"), (1e6, String::from("")));
+        // only known units lose their prefixes
+        assert_eq!(normalize_unit("Kelvin"), (1.0, String::from("Kelvin")));
+        assert_eq!(normalize_unit("GC pauses"), (1.0, String::from("GC pauses")));
+        assert_eq!(normalize_unit("requests"), (1.0, String::from("requests")));
+    }
+
+    #[test]
+    fn json_lines_on_stdout() {
+        let out = r#"warming up...
+{"metric": "latency", "value": 1.5, "unit": "ms", "lower_is_better": true}
+  {"metric": "throughput", "value": 2000, "lower_is_better": false}
+{"metric": "incomplete"}
+{"unrelated": "json"}
+not json at all"#;
+        let res = metrics(&bench(OutputFormat::JsonLines, &[]), out.lines());
+        assert_eq!(res.len(), 2);
+        assert_eq!(
+            res["bench/latency"],
+            Measurement {
+                value: 1.5,
+                unit: Some(String::from("ms")),
+                deviation: None,
+                ci: None,
+                lower_is_better: Some(true),
+            }
+        );
+        assert_eq!(res["bench/throughput"].value, 2000.0);
+        assert_eq!(res["bench/throughput"].unit, None);
+        assert_eq!(res["bench/throughput"].lower_is_better, Some(false));
+
+        // records are only picked up from benchmarks that use the protocol
+        assert!(metrics(&bench(OutputFormat::Regex, &[]), out.lines()).is_empty());
+    }
+
+    #[test]
+    fn metrics_file() {
+        let b = bench(OutputFormat::JsonLines, &[]);
+        let dir = env::temp_dir().join(format!("taster-test-{}-metrics", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let f = MetricsFile::create(dir.join("metrics.jsonl")).unwrap();
+        // benchmarks don't have to write the file at all
+        assert!(f.read(&b).is_empty());
+        // nothing that is already there is used, not even a link to somewhere harmless
+        assert!(MetricsFile::create(f.path.clone()).is_err());
+        symlink(dir.join("elsewhere"), dir.join("link.jsonl")).unwrap();
+        assert!(MetricsFile::create(dir.join("link.jsonl")).is_err());
+        assert!(!dir.join("elsewhere").exists());
+
+        fs::write(
+            &f.path,
+            "{\"metric\": \"rss\", \"value\": 12.5, \"unit\": \"MiB\"}\n\
+             \n\
+             garbage\n\
+             {\"metric\": \"ops\", \"value\": 3e6}\n",
+        ).unwrap();
+        let res = f.read(&b);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res["bench/rss"].value, 12.5);
+        assert_eq!(res["bench/rss"].unit, Some(String::from("MiB")));
+        assert_eq!(res["bench/ops"].value, 3e6);
+
+        let path = f.path.clone();
+        drop(f);
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_fields("a,b,,c"), vec!["a", "b", "", "c"]);
+        assert_eq!(csv_fields(r#""a,b",c"#), vec!["a,b", "c"]);
+        assert_eq!(csv_fields(r#""say ""hi""",2"#), vec![r#"say "hi""#, "2"]);
+        assert_eq!(csv_fields(r#""""#), vec![""]);
+        assert_eq!(csv_fields(""), vec![""]);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let src = "name,\"time, ms\",ops\nparse,\"1,234\",5\n\n\"load, cold\",7,n/a\n";
+        let cols = vec![String::from("time, ms"), String::from("ops")];
+        let b = bench(OutputFormat::Regex, &[]);
+
+        let mut res = HashMap::new();
+        csv_metrics(&b, src, &cols, Some(&String::from("name")), &mut res).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res["bench/parse/time, ms"].value, 1234.0);
+        assert_eq!(res["bench/parse/ops"].value, 5.0);
+        assert_eq!(res["bench/load, cold/time, ms"].value, 7.0);
+
+        // without a name column, later rows win
+        let mut res = HashMap::new();
+        csv_metrics(&b, src, &cols, None, &mut res).unwrap();
+        assert_eq!(res["bench/time, ms"].value, 7.0);
+        assert_eq!(res["bench/ops"].value, 5.0);
+
+        let missing = vec![String::from("latency")];
+        assert!(csv_metrics(&b, src, &missing, None, &mut HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn json_pointers() {
+        let src = r#"{"results": [{"mean": 1.5}, {"mean": "2,500"}], "name": "x", "a/b": 3}"#;
+        let pointers = [
+            ("first", "/results/0/mean"),
+            ("second", "/results/1/mean"),
+            ("escaped", "/a~1b"),
+            ("name", "/name"),
+            ("missing", "/results/2/mean"),
+        ].iter()
+            .map(|&(n, p)| (String::from(n), String::from(p)))
+            .collect::<Vec<_>>();
+        let b = bench(OutputFormat::Regex, &[]);
+
+        let mut res = HashMap::new();
+        json_metrics(&b, src, &pointers, &mut res).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res["bench/first"].value, 1.5);
+        assert_eq!(res["bench/second"].value, 2500.0);
+        assert_eq!(res["bench/escaped"].value, 3.0);
+
+        assert!(json_metrics(&b, "{", &pointers, &mut HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn result_files_stay_in_workdir() {
+        let dir = env::temp_dir().join(format!("taster-test-{}-results", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let workdir = dir.join("work");
+        let outside = dir.join("outside");
+        fs::create_dir_all(workdir.join("out")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        let csv = "metric,value\n";
+        fs::write(workdir.join("out").join("a.csv"), format!("{}a,1\n", csv)).unwrap();
+        fs::write(outside.join("b.csv"), format!("{}b,2\n", csv)).unwrap();
+        #[cfg(unix)]
+        ::std::os::unix::fs::symlink(outside.join("b.csv"), workdir.join("out").join("c.csv"))
+            .unwrap();
+
+        let mut b = bench(OutputFormat::Regex, &[]);
+        b.result_files = ["out/*.csv", "../outside/*.csv", "out/../../outside/b.csv"]
+            .iter()
+            .map(|p| ResultFile {
+                path: String::from(*p),
+                format: ResultFormat::Csv {
+                    columns: vec![String::from("value")],
+                    name_column: Some(String::from("metric")),
+                },
+            })
+            .collect();
+
+        let since = SystemTime::now() - Duration::from_secs(60);
+        let (res, read) = result_files(&b, &workdir, since);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res["bench/a/value"].value, 1.0);
+        assert_eq!(read, vec![workdir.canonicalize().unwrap().join("out").join("a.csv")]);
+
+        // files from before the run are stale
+        let (res, read) = result_files(&b, &workdir, SystemTime::now() + Duration::from_secs(60));
+        assert!(res.is_empty() && read.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn named_captures() {
+        let b = bench(
+            OutputFormat::Regex,
+            &[
+                r"(?P<metric>\w+) took (?P<value>[0-9.,]+) ?(?P<unit>[a-zµ]*)",
+                r"^rss: (?P<value>\d+)$",
+                r"^(\w+)=(\d+)$",
+                r"^total (\d+)$",
+            ],
+        );
+        let out = "parse took 1.5 ms, load took 2,000 us\nrss: 512\nops=7\ntotal 9\ncopy took 3";
+        let res = metrics(&b, out.lines());
+        assert_eq!(res.len(), 6);
+        assert_eq!(res["bench/parse"].value, 1.5);
+        assert_eq!(res["bench/parse"].unit, Some(String::from("ms")));
+        assert_eq!(res["bench/load"].value, 2000.0);
+        assert_eq!(res["bench/load"].unit, Some(String::from("us")));
+        // without a `metric` or `unit` group, the regex's index names the metric
+        assert_eq!(res["bench/1"], Measurement::new(512.0));
+        assert_eq!(res["bench/ops"].value, 7.0);
+        assert_eq!(res["bench/3"].value, 9.0);
+        // an empty `unit` group means there is no unit
+        assert_eq!(res["bench/copy"].unit, None);
+    }
+
+    #[test]
+    fn normalized_measurements() {
+        let m = Measurement {
+            value: 1.5,
+            unit: Some(String::from("ms/iter")),
+            deviation: Some(0.5),
+            ci: Some((1.0, 2.0)),
+            lower_is_better: Some(true),
+        };
+        assert_eq!(
+            m.normalized(),
+            Measurement {
+                value: 1.5e6,
+                unit: Some(String::from("ns/iter")),
+                deviation: Some(0.5e6),
+                ci: Some((1e6, 2e6)),
+                lower_is_better: Some(true),
+            }
+        );
+
+        let mut m = Measurement::new(2.0);
+        m.unit = Some(String::from("K"));
+        assert_eq!(m.normalized(), Measurement::new(2000.0));
+        assert_eq!(Measurement::new(3.0).normalized(), Measurement::new(3.0));
+
+        // values from differently-prefixed units end up comparable
+        let mut a = Measurement::new(1.0);
+        a.unit = Some(String::from("MB/s"));
+        let mut b = Measurement::new(1000.0);
+        b.unit = Some(String::from("kB/s"));
+        assert_eq!(a.normalized(), b.normalized());
+    }
+
+    #[test]
+    fn display_units() {
+        assert_eq!(display_unit(1007.0, "ns/iter"), (1e3, String::from("µs/iter")));
+        assert_eq!(display_unit(2.5e6, "ns"), (1e6, String::from("ms")));
+        assert_eq!(display_unit(-3e9, "ns"), (1e9, String::from("s")));
+        assert_eq!(display_unit(999.0, "ns"), (1.0, String::from("ns")));
+        assert_eq!(display_unit(5e6, "B/s"), (1.0, String::from("B/s")));
+    }
+}
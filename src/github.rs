@@ -1,6 +1,10 @@
 use Push;
 use Commit;
+use bisect::Culprit;
 use config::Config;
+use logs::LogLinks;
+use notifier::Notifier;
+use process::Outcome;
 use taste::TastingResult;
 use github_rs::StatusCode;
 use github_rs::client::{Executor, Github};
@@ -8,6 +12,9 @@ use serde_json;
 
 pub struct GithubNotifier {
     api_token: String,
+    repo_url: String,
+    /// Used to link each status to the commit's logs, if Taster is publicly reachable.
+    log_links: Option<LogLinks>,
 }
 
 #[derive(Deserialize)]
@@ -20,18 +27,62 @@ struct Payload {
     state: String,
     description: String,
     context: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_url: Option<String>,
+}
+
+/// GitHub rejects status descriptions longer than 140 characters.
+fn truncate_description(description: String) -> String {
+    if description.chars().count() > 140 {
+        description.chars().take(137).collect::<String>() + "..."
+    } else {
+        description
+    }
+}
+
+/// Returns the outcome of the first stage of `res` that didn't succeed, and a description of it.
+fn first_failure(res: &TastingResult) -> Option<(&Outcome, String)> {
+    if !res.build.success() {
+        return Some((&res.build, format!("Build {}.", res.build)));
+    }
+    if !res.test.success() {
+        return Some((&res.test, format!("Tests {}.", res.test)));
+    }
+    if let Some(ref r) = res.results {
+        for &(ref bm, ref outcome, _) in r {
+            if !outcome.success() {
+                return Some((outcome, format!("Benchmark {} {}.", bm.name, outcome)));
+            }
+        }
+    }
+    None
 }
 
 impl GithubNotifier {
-    pub fn new(api_token: &str) -> GithubNotifier {
+    pub fn new(api_token: &str, repo_url: &str, log_links: Option<LogLinks>) -> GithubNotifier {
         GithubNotifier {
             api_token: String::from(api_token),
+            repo_url: String::from(repo_url),
+            log_links: log_links,
         }
     }
 
+    /// Returns the URL of the logs of `commit` tasted for `push`, if we serve them publicly.
+    fn logs_url(&self, push: &Push, commit: &Commit) -> Option<String> {
+        self.log_links.as_ref().map(|l| {
+            l.commit_url(
+                &self.repo_url,
+                push.push_ref.as_ref().map(|r| r.as_str()),
+                commit.id,
+            )
+        })
+    }
+
     fn post_status(&self, push: &Push, commit: &Commit, payload: Payload) -> Result<(), String> {
-        let owner_name = push.owner_name.clone().unwrap();
-        let repo_name = push.repo_name.clone().unwrap();
+        let (owner_name, repo_name) = match (push.owner_name.clone(), push.repo_name.clone()) {
+            (Some(o), Some(r)) => (o, r),
+            _ => return Err(String::from("push does not identify a GitHub repository")),
+        };
         println!(
             "Setting status of {}/{}#{} to {}",
             owner_name,
@@ -40,7 +91,8 @@ impl GithubNotifier {
             payload.state
         );
 
-        let client = Github::new(self.api_token.clone()).unwrap();
+        let client = Github::new(self.api_token.clone())
+            .map_err(|e| format!("Failed to create GitHub client: {}", e))?;
         let result = client
             .post(&payload)
             .repos()
@@ -61,42 +113,101 @@ impl GithubNotifier {
             Err(err) => Err(format!("Failed to execute GitHub request: {}", err)),
         }
     }
+}
 
-    pub fn notify_pending(&self, push: &Push, commit: &Commit) -> Result<(), String> {
+impl Notifier for GithubNotifier {
+    fn name(&self) -> &str {
+        "GitHub"
+    }
+
+    fn notify_pending(&self, push: &Push, commit: &Commit) -> Result<(), String> {
         let payload = Payload {
             context: "Taster".to_string(),
             state: "pending".to_string(),
             description: "Currently tasting...".to_string(),
+            target_url: self.logs_url(push, commit),
         };
 
         self.post_status(push, commit, payload)
     }
 
-    pub fn notify(
+    fn notify_error(&self, push: &Push, commit: &Commit, err: &str) -> Result<(), String> {
+        let payload = Payload {
+            context: "Taster".to_string(),
+            state: "error".to_string(),
+            description: truncate_description(format!("Failed to taste: {}", err)),
+            target_url: self.logs_url(push, commit),
+        };
+
+        self.post_status(push, commit, payload)
+    }
+
+    fn notify_superseded(&self, push: &Push, commit: &Commit) -> Result<(), String> {
+        let payload = Payload {
+            context: "Taster".to_string(),
+            state: "error".to_string(),
+            description: "Superseded by a newer push.".to_string(),
+            target_url: None,
+        };
+
+        self.post_status(push, commit, payload)
+    }
+
+    fn notify_culprit(
+        &self,
+        _cfg: Option<&Config>,
+        culprit: &Culprit,
+        push: &Push,
+    ) -> Result<(), String> {
+        let description = match culprit.change {
+            Some(c) => format!("Regressed {} by {:+.2}%.", culprit.metric, c * 100.0),
+            None => format!("Broke {}.", culprit.benchmark),
+        };
+        let payload = Payload {
+            context: "Taster/bisect".to_string(),
+            state: "failure".to_string(),
+            description: description,
+            target_url: self.logs_url(push, &culprit.commit),
+        };
+
+        self.post_status(push, &culprit.commit, payload)
+    }
+
+    fn notify(
         &self,
         _cfg: Option<&Config>,
         res: &TastingResult,
         push: &Push,
         commit: &Commit,
     ) -> Result<(), String> {
-        let state = if !res.build || !res.test || !res.bench {
-            "failure"
-        } else {
-            "success"
+        let failure = first_failure(res);
+        let state = match failure {
+            // we couldn't run the stage at all, which says nothing about the commit
+            Some((&Outcome::SpawnError(_), _)) => "error",
+            Some(_) => "failure",
+            // e.g., because there is no (valid) taster.toml
+            None if !res.bench => "failure",
+            None => "success",
         };
 
-        let taste = if !res.build || !res.bench {
+        let taste = if !res.build.success() || !res.bench {
             "was inedible"
-        } else if !res.test {
+        } else if !res.test.success() {
             "had a mixed palate"
         } else {
             "tasted nice"
         };
 
+        let description = match (res.config_error.as_ref(), failure) {
+            (Some(e), _) => format!("Invalid taster.toml: {}", e),
+            (None, Some((_, f))) => format!("It {}. {}", taste, f),
+            (None, None) => format!("It {}.", taste),
+        };
         let payload = Payload {
             context: "Taster".to_string(),
             state: state.to_string(),
-            description: format!("It {}.", taste),
+            description: truncate_description(description),
+            target_url: self.logs_url(push, commit),
         };
 
         self.post_status(push, commit, payload)
@@ -0,0 +1,208 @@
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single tasting of a commit, as recorded in the on-disk history.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tasting {
+    pub repo: String,
+    /// Full ref the commit was tasted on (e.g., `refs/heads/master`), if any.
+    pub push_ref: Option<String>,
+    pub commit: String,
+    pub build: bool,
+    pub test: bool,
+    pub bench: bool,
+    /// Seconds since the UNIX epoch at which the tasting finished.
+    pub timestamp: u64,
+    /// Benchmark name -> metric name -> samples.
+    pub benchmarks: HashMap<String, HashMap<String, Vec<f64>>>,
+}
+
+/// Append-only store of tasting results, kept as one JSON document per line so that a crash
+/// while writing can at most lose the last record.
+pub struct History {
+    path: PathBuf,
+    repo: String,
+    tastings: Vec<Tasting>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl History {
+    /// Opens the history stored at `path`, creating it if it does not exist yet. Only records for
+    /// `repo` are retained in memory.
+    pub fn open(path: &Path, repo: &str) -> Result<History, String> {
+        if let Some(dir) = path.parent() {
+            if !dir.is_dir() {
+                fs::create_dir_all(dir).map_err(|e| {
+                    format!("failed to create history directory {}: {}", dir.display(), e)
+                })?;
+            }
+        }
+
+        let mut tastings = Vec::new();
+        if path.exists() {
+            let f = File::open(path)
+                .map_err(|e| format!("failed to open history {}: {}", path.display(), e))?;
+            for (i, line) in BufReader::new(f).lines().enumerate() {
+                let line = line.map_err(|e| {
+                    format!("failed to read history {}: {}", path.display(), e)
+                })?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Tasting>(&line) {
+                    Ok(t) => if t.repo == repo {
+                        tastings.push(t)
+                    },
+                    Err(e) => println!(
+                        "ignoring malformed record on line {} of {}: {}",
+                        i + 1,
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        println!(
+            "Loaded {} previous tastings from {}",
+            tastings.len(),
+            path.display()
+        );
+
+        Ok(History {
+            path: path.to_path_buf(),
+            repo: String::from(repo),
+            tastings: tastings,
+        })
+    }
+
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Appends `tasting` to the on-disk history and makes it available for lookups.
+    pub fn record(&mut self, tasting: Tasting) -> Result<(), String> {
+        let line = serde_json::to_string(&tasting)
+            .map_err(|e| format!("failed to serialize tasting: {}", e))?;
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("failed to open history {}: {}", self.path.display(), e))?;
+        writeln!(f, "{}", line)
+            .and_then(|_| f.sync_data())
+            .map_err(|e| format!("failed to write history {}: {}", self.path.display(), e))?;
+        self.tastings.push(tasting);
+        Ok(())
+    }
+
+    /// Returns true if `commit` has been tasted on `push_ref` before.
+    pub fn contains(&self, push_ref: Option<&str>, commit: &str) -> bool {
+        self.tastings
+            .iter()
+            .any(|t| t.commit == commit && t.push_ref.as_ref().map(|s| s.as_str()) == push_ref)
+    }
+
+    /// Returns the most recently recorded samples of `metric` for `benchmark` at `commit`, on any
+    /// ref. Tastings in which the benchmark failed or did not report `metric` are skipped.
+    pub fn results_for(&self, commit: &str, benchmark: &str, metric: &str) -> Option<&Vec<f64>> {
+        self.tastings
+            .iter()
+            .rev()
+            .filter(|t| t.commit == commit)
+            .filter_map(|t| t.benchmarks.get(benchmark))
+            .filter_map(|r| r.get(metric))
+            .filter(|s| !s.is_empty())
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn tasting(repo: &str, push_ref: Option<&str>, commit: &str, samples: Vec<f64>) -> Tasting {
+        let mut metrics = HashMap::new();
+        metrics.insert(String::from("time"), samples);
+        let mut benchmarks = HashMap::new();
+        benchmarks.insert(String::from("bench"), metrics);
+        Tasting {
+            repo: String::from(repo),
+            push_ref: push_ref.map(String::from),
+            commit: String::from(commit),
+            build: true,
+            test: true,
+            bench: true,
+            timestamp: now(),
+            benchmarks: benchmarks,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = env::temp_dir().join(format!("taster-test-{}-history", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // the directory is created on demand
+        let path = dir.join("nested").join("history.jsonl");
+
+        let mut h = History::open(&path, "org/repo").unwrap();
+        assert_eq!(h.repo(), "org/repo");
+        assert!(!h.contains(Some("refs/heads/master"), "abc"));
+        h.record(tasting("org/repo", Some("refs/heads/master"), "abc", vec![1.0, 2.0]))
+            .unwrap();
+        h.record(tasting("org/other", Some("refs/heads/master"), "def", vec![3.0]))
+            .unwrap();
+        h.record(tasting("org/repo", None, "abc", vec![])).unwrap();
+
+        // a torn or garbled line doesn't take the rest of the history down with it
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(f, "{{\"repo\": \"org/repo\", \"commit\": ").unwrap();
+        writeln!(f).unwrap();
+        drop(f);
+        let mut h = History::open(&path, "org/repo").unwrap();
+        h.record(tasting("org/repo", Some("refs/heads/dev"), "abc", vec![4.0]))
+            .unwrap();
+
+        let h = History::open(&path, "org/repo").unwrap();
+        assert_eq!(h.tastings.len(), 3);
+        assert!(h.contains(Some("refs/heads/master"), "abc"));
+        assert!(h.contains(Some("refs/heads/dev"), "abc"));
+        assert!(h.contains(None, "abc"));
+        assert!(!h.contains(Some("refs/heads/other"), "abc"));
+        // only the requested repo's records are kept
+        assert!(!h.contains(Some("refs/heads/master"), "def"));
+
+        // the latest non-empty samples win, on whatever ref they were recorded
+        assert_eq!(h.results_for("abc", "bench", "time"), Some(&vec![4.0]));
+        assert_eq!(h.results_for("abc", "bench", "rss"), None);
+        assert_eq!(h.results_for("abc", "other", "time"), None);
+        assert_eq!(h.results_for("def", "bench", "time"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_empty_samples() {
+        let dir = env::temp_dir().join(format!("taster-test-{}-empty-samples", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("history.jsonl");
+
+        let mut h = History::open(&path, "org/repo").unwrap();
+        h.record(tasting("org/repo", None, "abc", vec![1.0])).unwrap();
+        h.record(tasting("org/repo", None, "abc", vec![])).unwrap();
+        assert_eq!(h.results_for("abc", "bench", "time"), Some(&vec![1.0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
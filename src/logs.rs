@@ -0,0 +1,667 @@
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use git2;
+use serde_json;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use extract::MetricsFile;
+use history;
+use process::{Finished, Outcome, Tee};
+use repo;
+
+/// Where and for how long the output of tasting stages is kept. Logs are stored as
+/// `<dir>/<repo>/<branch>/<commit>/<stage>.{stdout,stderr}.log`, with the metadata describing
+/// each stage's run in `<stage>.json` and the result files it wrote in `<stage>.results/` next
+/// to them.
+#[derive(Clone, Debug)]
+pub struct LogStore {
+    pub dir: PathBuf,
+    /// Logs older than this are deleted.
+    pub retention: Option<Duration>,
+    /// Once the logs take up more than this many bytes, the oldest commits' logs are deleted.
+    pub max_size: Option<u64>,
+    /// Logs older than this are gzip-compressed.
+    pub compress_after: Option<Duration>,
+}
+
+/// Describes a stage's run, as stored next to its logs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StageMeta {
+    pub stage: String,
+    pub command: Vec<String>,
+    /// Environment variables set for the stage on top of those Taster runs with.
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    /// Seconds since the UNIX epoch at which the stage started.
+    pub started: u64,
+    pub duration_secs: f64,
+    pub outcome: Outcome,
+}
+
+/// The log directory of a single commit.
+pub struct CommitLogs {
+    dir: PathBuf,
+}
+
+/// A commit whose logs are stored. The names are those of the directories they're stored in.
+pub struct StoredTasting {
+    pub repo: String,
+    pub branch: String,
+    pub commit: String,
+    pub modified: SystemTime,
+}
+
+/// The logs of one stage in a commit's log directory.
+pub struct StageLogs {
+    /// Name of the stage's files (without the extension).
+    pub name: String,
+    /// Only present once the stage has finished.
+    pub meta: Option<StageMeta>,
+}
+
+/// Links to the pages on which Taster serves logs (see `server`).
+#[derive(Clone, Debug)]
+pub struct LogLinks {
+    /// Public URL of Taster's HTTP server.
+    pub base_url: String,
+}
+
+/// A commit's log directory, as found when pruning.
+struct CommitDir {
+    path: PathBuf,
+    /// When a stage last wrote to it; compressing logs does not count.
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Whether `name` could have been produced by `component`, so that it is safe to use as a path
+/// component within the log directory.
+fn valid_component(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/') && !name.contains('\\')
+}
+
+/// Returns the names of the directories holding `commit`'s logs, relative to the store's root.
+fn commit_components(
+    repo_url: &str,
+    push_ref: Option<&str>,
+    commit: git2::Oid,
+) -> (String, String, String) {
+    let branch = match push_ref {
+        None => String::from("_none"),
+        Some(r) if r.starts_with("refs/heads/") => component(&r["refs/heads/".len()..]),
+        Some(r) => component(r.trim_start_matches("refs/")),
+    };
+    (
+        component(&repo::full_name(repo_url)),
+        branch,
+        commit.to_string(),
+    )
+}
+
+/// Turns `s` into a single path component, percent-encoding anything that could be
+/// misinterpreted, such as the `/` in branch names or a leading `.`.
+fn component(s: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' || (c == '.' && i > 0) {
+            out.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+    out
+}
+
+/// Returns the total size of the files directly in `dir`.
+fn files_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let meta = entry?.metadata()?;
+        if meta.is_file() {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Replaces the file at `path` with a gzip-compressed copy named `<path>.gz`, returning the
+/// number of bytes saved.
+fn compress(path: &Path) -> io::Result<u64> {
+    let mut input = Vec::new();
+    File::open(path)?.read_to_end(&mut input)?;
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+    encoder.write_all(&input)?;
+    let compressed = encoder.finish()?.metadata()?.len();
+    fs::remove_file(path)?;
+    Ok((input.len() as u64).saturating_sub(compressed))
+}
+
+impl LogStore {
+    /// Returns the log directory for `commit`, tasted on `push_ref` in the repository at
+    /// `repo_url`. Branches are named without their `refs/heads/` prefix; other refs (such as
+    /// tags and pull requests) without `refs/`.
+    pub fn for_commit(
+        &self,
+        repo_url: &str,
+        push_ref: Option<&str>,
+        commit: git2::Oid,
+    ) -> CommitLogs {
+        let (repo, branch, commit) = commit_components(repo_url, push_ref, commit);
+        CommitLogs {
+            dir: self.dir.join(repo).join(branch).join(commit),
+        }
+    }
+
+    /// Returns the stored logs of the commit in the directory `<repo>/<branch>/<commit>`, if
+    /// there are any.
+    pub fn find(&self, repo: &str, branch: &str, commit: &str) -> Option<CommitLogs> {
+        if ![repo, branch, commit].iter().all(|c| valid_component(c)) {
+            return None;
+        }
+        let dir = self.dir.join(repo).join(branch).join(commit);
+        if dir.is_dir() {
+            Some(CommitLogs { dir: dir })
+        } else {
+            None
+        }
+    }
+
+    /// Lists the commits whose logs are stored, most recently tasted first.
+    pub fn tastings(&self) -> io::Result<Vec<StoredTasting>> {
+        let mut tastings = self.commit_dirs()?
+            .into_iter()
+            .filter_map(|c| {
+                let rel = c.path.strip_prefix(&self.dir).ok()?.to_owned();
+                let mut names = rel.iter().map(|n| n.to_string_lossy().into_owned());
+                Some(StoredTasting {
+                    repo: names.next()?,
+                    branch: names.next()?,
+                    commit: names.next()?,
+                    modified: c.modified,
+                })
+            })
+            .collect::<Vec<_>>();
+        tastings.sort_by(|a, b| b.modified.cmp(&a.modified));
+        Ok(tastings)
+    }
+
+    fn commit_dirs(&self) -> io::Result<Vec<CommitDir>> {
+        let mut commits = Vec::new();
+        if !self.dir.is_dir() {
+            return Ok(commits);
+        }
+        for repo in subdirs(&self.dir)? {
+            for branch in subdirs(&repo)? {
+                for commit in subdirs(&branch)? {
+                    let mut modified = UNIX_EPOCH;
+                    let mut size = 0;
+                    for entry in fs::read_dir(&commit)? {
+                        let entry = entry?;
+                        let meta = entry.metadata()?;
+                        if meta.is_dir() {
+                            // archived result files
+                            size += files_size(&entry.path())?;
+                            continue;
+                        }
+                        size += meta.len();
+                        if entry.path().extension().map_or(true, |e| e != "gz") {
+                            modified = cmp::max(modified, meta.modified()?);
+                        }
+                    }
+                    commits.push(CommitDir {
+                        path: commit,
+                        modified: modified,
+                        size: size,
+                    });
+                }
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Applies the retention policy: deletes the logs of commits that are too old or, oldest
+    /// first, as many as needed to stay within the size limit, and compresses the rest once they
+    /// are old enough.
+    pub fn prune(&self) {
+        let mut commits = match self.commit_dirs() {
+            Ok(c) => c,
+            Err(e) => {
+                println!("failed to scan logs in {}: {}", self.dir.display(), e);
+                return;
+            }
+        };
+        commits.sort_by_key(|c| c.modified);
+
+        let now = SystemTime::now();
+        let older_than = |c: &CommitDir, limit: Option<Duration>| match limit {
+            None => false,
+            Some(l) => now.duration_since(c.modified).map(|age| age > l).unwrap_or(false),
+        };
+        let mut total = commits.iter().map(|c| c.size).sum::<u64>();
+        for c in commits.iter() {
+            let too_big = self.max_size.map_or(false, |m| total > m);
+            if too_big || older_than(c, self.retention) {
+                match fs::remove_dir_all(&c.path) {
+                    Ok(()) => {
+                        println!("removed logs in {}", c.path.display());
+                        total -= c.size;
+                    }
+                    Err(e) => println!("failed to remove logs in {}: {}", c.path.display(), e),
+                }
+                // clean up the branch directory if that was its last commit; this fails (and
+                // is meant to) if there are others
+                if let Some(branch) = c.path.parent() {
+                    let _ = fs::remove_dir(branch);
+                }
+            } else if older_than(c, self.compress_after) {
+                let logs = match fs::read_dir(&c.path) {
+                    Ok(entries) => entries
+                        .filter_map(|e| e.ok().map(|e| e.path()))
+                        .filter(|p| p.extension().map_or(false, |e| e == "log"))
+                        .collect::<Vec<_>>(),
+                    Err(_) => continue,
+                };
+                for log in logs {
+                    match compress(&log) {
+                        Ok(saved) => total -= cmp::min(saved, total),
+                        Err(e) => println!("failed to compress {}: {}", log.display(), e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CommitLogs {
+    /// Creates the log files for `stage`, to which its output is written as it runs. Failing to
+    /// do so is reported, but doesn't fail the tasting.
+    pub fn tee(&self, stage: &str) -> Option<Tee> {
+        match self.try_tee(stage) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                println!(
+                    "failed to create logs for {} in {}: {}",
+                    stage,
+                    self.dir.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn try_tee(&self, stage: &str) -> io::Result<Tee> {
+        fs::create_dir_all(&self.dir)?;
+        let name = component(stage);
+        // logs from an earlier tasting of the same commit are now stale
+        for stale in &[
+            format!("{}.json", name),
+            format!("{}.stdout.log.gz", name),
+            format!("{}.stderr.log.gz", name),
+        ] {
+            let _ = fs::remove_file(self.dir.join(stale));
+        }
+        let _ = fs::remove_dir_all(self.dir.join(format!("{}.results", name)));
+        Ok(Tee {
+            stdout: File::create(self.dir.join(format!("{}.stdout.log", name)))?,
+            stderr: File::create(self.dir.join(format!("{}.stderr.log", name)))?,
+        })
+    }
+
+    /// Creates the file to which `stage` may write JSON-lines metric records. It is kept with the
+    /// logs, where nobody else can put anything, but its name starts with a `.` so that it is
+    /// neither listed nor served with them.
+    pub fn metrics_file(&self, stage: &str) -> io::Result<MetricsFile> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!(".{}.metrics.jsonl", component(stage)));
+        // left behind if we crashed during an earlier run of the stage
+        let _ = fs::remove_file(&path);
+        MetricsFile::create(path)
+    }
+
+    /// Stores the metadata of `stage` once it has finished.
+    pub fn finish(&self, stage: &str, run: &Finished) {
+        if let Err(e) = self.write_meta(stage, run) {
+            println!(
+                "failed to store metadata for {} in {}: {}",
+                stage,
+                self.dir.display(),
+                e
+            );
+        }
+    }
+
+    fn write_meta(&self, stage: &str, run: &Finished) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let name = component(stage);
+        let meta = StageMeta {
+            stage: String::from(stage),
+            command: run.command.clone(),
+            env: run.env.iter().cloned().collect(),
+            cwd: run.cwd.clone(),
+            started: history::now().saturating_sub(run.duration.as_secs()),
+            duration_secs: run.duration.as_secs() as f64
+                + run.duration.subsec_nanos() as f64 / 1_000_000_000.0,
+            outcome: run.outcome.clone(),
+        };
+        let f = File::create(self.dir.join(format!("{}.json", name)))?;
+        serde_json::to_writer_pretty(f, &meta).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Lists the stages that have logs here, in the order they ran; stages that are still
+    /// running come last.
+    pub fn stages(&self) -> io::Result<Vec<StageLogs>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                continue;
+            }
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if file.starts_with('.') {
+                // a running stage's metrics file
+                continue;
+            }
+            let name = file.trim_end_matches(".gz")
+                .trim_end_matches(".log")
+                .trim_end_matches(".stdout")
+                .trim_end_matches(".stderr")
+                .trim_end_matches(".json");
+            if !names.iter().any(|n| n == name) {
+                names.push(String::from(name));
+            }
+        }
+        let mut stages = names
+            .into_iter()
+            .map(|name| StageLogs {
+                meta: self.meta(&name),
+                name: name,
+            })
+            .collect::<Vec<_>>();
+        stages.sort_by_key(|s| match s.meta {
+            Some(ref m) => (0, m.started),
+            None => (1, 0),
+        });
+        Ok(stages)
+    }
+
+    /// Returns the metadata of the stage whose files are called `name`, once it has finished.
+    pub fn meta(&self, name: &str) -> Option<StageMeta> {
+        if !valid_component(name) {
+            return None;
+        }
+        File::open(self.dir.join(format!("{}.json", name)))
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+    }
+
+    /// Copies the result `files` that `stage` wrote in `workdir` to `<stage>.results/`, naming
+    /// each after its path relative to the workdir. Failing to do so is reported, but doesn't
+    /// fail the tasting.
+    pub fn archive(&self, stage: &str, workdir: &Path, files: &[PathBuf]) {
+        if files.is_empty() {
+            return;
+        }
+        let dir = self.dir.join(format!("{}.results", component(stage)));
+        let root = workdir.canonicalize().unwrap_or(workdir.to_path_buf());
+        for f in files {
+            let rel = f.strip_prefix(&root).unwrap_or(f);
+            let res = fs::create_dir_all(&dir)
+                .and_then(|_| fs::copy(f, dir.join(component(&rel.to_string_lossy()))));
+            if let Err(e) = res {
+                println!("failed to archive {} in {}: {}", f.display(), dir.display(), e);
+            }
+        }
+    }
+
+    /// Lists the result files archived for the stage whose files are called `name`.
+    pub fn results(&self, name: &str) -> Vec<String> {
+        if !valid_component(name) {
+            return vec![];
+        }
+        let mut files = fs::read_dir(self.dir.join(format!("{}.results", name)))
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    /// Reads the result file `file` archived for the stage whose files are called `name`.
+    pub fn read_result(&self, name: &str, file: &str) -> io::Result<Vec<u8>> {
+        if !valid_component(name) || !valid_component(file) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such result file"));
+        }
+        let mut buf = Vec::new();
+        File::open(self.dir.join(format!("{}.results", name)).join(file))?
+            .read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Whether the stage whose files are called `name` has finished.
+    pub fn finished(&self, name: &str) -> bool {
+        valid_component(name) && self.dir.join(format!("{}.json", name)).is_file()
+    }
+
+    /// Reads `stream` (`stdout` or `stderr`) of the stage whose files are called `name`, starting
+    /// at byte `offset`. Compressed logs are decompressed.
+    pub fn read(&self, name: &str, stream: &str, offset: u64) -> io::Result<Vec<u8>> {
+        if !valid_component(name) || (stream != "stdout" && stream != "stderr") {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such log"));
+        }
+        let path = self.dir.join(format!("{}.{}.log", name, stream));
+        let mut buf = Vec::new();
+        match File::open(&path) {
+            Ok(mut f) => {
+                f.seek(SeekFrom::Start(offset))?;
+                f.read_to_end(&mut buf)?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let gz = File::open(self.dir.join(format!("{}.{}.log.gz", name, stream)))?;
+                GzDecoder::new(gz).read_to_end(&mut buf)?;
+                buf = buf.split_off(cmp::min(offset as usize, buf.len()));
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(buf)
+    }
+}
+
+impl LogLinks {
+    /// Returns the URL of the page listing the logs of `commit`, tasted on `push_ref` in the
+    /// repository at `repo_url`.
+    pub fn commit_url(&self, repo_url: &str, push_ref: Option<&str>, commit: git2::Oid) -> String {
+        let (repo, branch, commit) = commit_components(repo_url, push_ref, commit);
+        // the directory names may contain `%`, which needs escaping in URLs
+        format!(
+            "{}/logs/{}/{}/{}",
+            self.base_url.trim_end_matches('/'),
+            repo.replace('%', "%25"),
+            branch.replace('%', "%25"),
+            commit
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn store(name: &str) -> LogStore {
+        let dir = env::temp_dir().join(format!("taster-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        LogStore {
+            dir: dir,
+            retention: None,
+            max_size: None,
+            compress_after: None,
+        }
+    }
+
+    /// Stores a finished stage for the `n`th commit, whose files were last written `days` ago.
+    fn tasting(logs: &LogStore, n: u8, days: u64) -> CommitLogs {
+        let oid = git2::Oid::from_bytes(&[n; 20]).unwrap();
+        let commit = logs.for_commit("https://github.com/o/r", Some("refs/heads/master"), oid);
+        let mut tee = commit.tee("build").unwrap();
+        tee.stdout.write_all(&[b'x'; 1000]).unwrap();
+        tee.stderr.write_all(b"warning").unwrap();
+        commit.finish(
+            "build",
+            &Finished {
+                command: vec![String::from("cargo"), String::from("build")],
+                env: vec![],
+                cwd: None,
+                outcome: Outcome::Success,
+                stdout: vec![],
+                stderr: vec![],
+                duration: Duration::from_secs(1),
+            },
+        );
+        let then = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        for entry in fs::read_dir(&commit.dir).unwrap() {
+            let f = File::options().write(true).open(entry.unwrap().path()).unwrap();
+            f.set_modified(then).unwrap();
+        }
+        commit
+    }
+
+    #[test]
+    fn components() {
+        assert_eq!(component("master"), "master");
+        assert_eq!(component("feature/x.y"), "feature%2Fx.y");
+        assert_eq!(component(".."), "%2E.");
+        assert_eq!(component(".hidden"), "%2Ehidden");
+        assert_eq!(component("a\\b c"), "a%5Cb%20c");
+        assert_eq!(component("größe"), "gr%C3%B6%C3%9Fe");
+        for s in &["master", "feature/x.y", "..", ".", ".hidden", "a\\b", "/"] {
+            assert!(valid_component(&component(s)), "{}", s);
+        }
+
+        for s in &["", ".", "..", ".hidden", "a/b", "/", "a\\b", "../x"] {
+            assert!(!valid_component(s), "{}", s);
+        }
+        assert!(valid_component("a..b"));
+
+        let oid = git2::Oid::from_bytes(&[1; 20]).unwrap();
+        let dirs = |push_ref| {
+            let (repo, branch, commit) =
+                commit_components("https://github.com/o/r", push_ref, oid);
+            assert_eq!(commit, oid.to_string());
+            format!("{}/{}", repo, branch)
+        };
+        assert_eq!(dirs(Some("refs/heads/feature/x")), "o%2Fr/feature%2Fx");
+        assert_eq!(dirs(Some("refs/pull/3/head")), "o%2Fr/pull%2F3%2Fhead");
+        assert_eq!(dirs(Some("refs/tags/v1.0")), "o%2Fr/tags%2Fv1.0");
+        assert_eq!(dirs(None), "o%2Fr/_none");
+
+        let links = LogLinks {
+            base_url: String::from("https://taster.example.com/"),
+        };
+        assert_eq!(
+            links.commit_url("https://github.com/o/r", Some("refs/heads/f/x"), oid),
+            format!("https://taster.example.com/logs/o%252Fr/f%252Fx/{}", oid)
+        );
+    }
+
+    #[test]
+    fn lookups_stay_inside_the_store() {
+        let logs = store("logs-lookup");
+        let commit = tasting(&logs, 1, 0);
+        let oid = git2::Oid::from_bytes(&[1; 20]).unwrap().to_string();
+        assert!(logs.find("o%2Fr", "master", &oid).is_some());
+        assert!(logs.find("o%2Fr", "master", "..").is_none());
+        assert!(logs.find("..", "..", "..").is_none());
+        assert!(logs.find("o%2Fr/master", &oid, ".").is_none());
+
+        assert!(commit.finished("build"));
+        assert!(!commit.finished("../build"));
+        assert!(commit.meta("build").is_some());
+        assert!(commit.meta("../master/build").is_none());
+        assert_eq!(commit.read("build", "stderr", 3).unwrap(), b"ning");
+        assert!(commit.read("build", "json", 0).is_err());
+        assert!(commit.read("../build", "stdout", 0).is_err());
+        assert!(commit.read_result("build", "../build.json").is_err());
+        assert!(commit.read_result("..", "build.json").is_err());
+
+        fs::remove_dir_all(&logs.dir).unwrap();
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let mut logs = store("logs-age");
+        logs.retention = Some(Duration::from_secs(7 * 24 * 60 * 60));
+        logs.compress_after = Some(Duration::from_secs(24 * 60 * 60));
+        let old = tasting(&logs, 1, 10);
+        let aging = tasting(&logs, 2, 3);
+        let fresh = tasting(&logs, 3, 0);
+        logs.prune();
+
+        assert!(!old.dir.exists());
+        assert!(aging.dir.join("build.stdout.log.gz").is_file());
+        assert!(!aging.dir.join("build.stdout.log").exists());
+        assert!(fresh.dir.join("build.stdout.log").is_file());
+        // compressed logs read just like uncompressed ones
+        assert_eq!(aging.read("build", "stdout", 990).unwrap(), vec![b'x'; 10]);
+        assert_eq!(aging.read("build", "stderr", 0).unwrap(), b"warning");
+        assert_eq!(
+            logs.tastings().unwrap().iter().map(|t| t.commit.clone()).collect::<Vec<_>>(),
+            vec![
+                git2::Oid::from_bytes(&[3; 20]).unwrap().to_string(),
+                git2::Oid::from_bytes(&[2; 20]).unwrap().to_string(),
+            ]
+        );
+
+        // compressing doesn't make the logs look newer (or older) on the next pass
+        logs.prune();
+        assert!(aging.dir.join("build.stdout.log.gz").is_file());
+
+        fs::remove_dir_all(&logs.dir).unwrap();
+    }
+
+    #[test]
+    fn prune_by_size() {
+        let mut logs = store("logs-size");
+        let commits = (1..4).map(|n| tasting(&logs, n, 4 - n as u64)).collect::<Vec<_>>();
+        let size = files_size(&commits[0].dir).unwrap();
+        // room for two commits and a bit, so the oldest has to go
+        logs.max_size = Some(2 * size + size / 2);
+        logs.prune();
+        assert!(!commits[0].dir.exists());
+        assert!(commits[1].dir.exists() && commits[2].dir.exists());
+
+        logs.max_size = Some(0);
+        logs.prune();
+        assert!(commits.iter().all(|c| !c.dir.exists()));
+        // as are the branch directories they leave empty
+        assert!(!commits[0].dir.parent().unwrap().exists());
+
+        fs::remove_dir_all(&logs.dir).unwrap();
+    }
+}
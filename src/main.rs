@@ -4,108 +4,326 @@ extern crate log;
 extern crate afterparty;
 #[macro_use]
 extern crate clap;
+extern crate flate2;
 extern crate git2;
 extern crate github_rs;
+extern crate glob;
+extern crate hmac;
 extern crate hyper;
+extern crate hyper_native_tls;
 extern crate lettre;
+extern crate lettre_email;
+extern crate libc;
+extern crate native_tls;
 extern crate regex;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 extern crate slack_hook;
 extern crate toml;
 
 mod auth;
+mod bisect;
+mod check;
 mod config;
 mod email;
+mod extract;
+mod history;
+mod logs;
+mod notifier;
+mod process;
+mod queue;
 mod repo;
+mod server;
+mod settings;
 mod slack;
+mod stats;
 mod taste;
 mod github;
+mod webhook;
 
 use afterparty::{Delivery, Event, Hub};
 use hyper::Server;
-use std::collections::HashMap;
 use std::error::Error;
 use std::path::Path;
+use std::collections::HashMap;
+use std::panic;
 use std::sync::{Arc, Mutex};
-
-use config::Config;
+use std::thread;
+use std::time::Duration;
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 const TASTER_USAGE: &'static str = "\
 EXAMPLES:
   taster -w /path/to/workdir -s my_secret
-  taster -l 0.0.0.0:1234 -w /path/to/workdir -s my_secret";
+  taster -l 0.0.0.0:1234 -w /path/to/workdir -s my_secret
+  taster check-config path/to/taster.toml --sample_output bench.log";
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Commit {
+    #[serde(serialize_with = "repo::serialize_oid", deserialize_with = "repo::deserialize_oid")]
     pub id: git2::Oid,
     pub msg: String,
     pub url: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Push {
     pub head_commit: Commit,
     pub push_ref: Option<String>,
+    /// For pull requests, the full ref of the branch the PR targets, against whose results the
+    /// PR's results are compared.
+    #[serde(default)]
+    pub base_ref: Option<String>,
     pub pusher: Option<String>,
     pub owner_name: Option<String>,
     pub repo_name: Option<String>,
 }
 
+/// A repository that we taste, and everything needed to taste it. The workspace is the main
+/// checkout, which we fetch into; jobs are tasted in worktrees created from it.
+struct RepoState {
+    settings: settings::RepoSettings,
+    ws: Mutex<repo::Workspace>,
+    history: Mutex<history::History>,
+    notifiers: Arc<notifier::NotifierRegistry>,
+}
+
+impl RepoState {
+    fn open(settings: settings::RepoSettings, log_links: Option<&logs::LogLinks>) -> RepoState {
+        let url = settings.url.as_str();
+        let ws = repo::Workspace::new(url, &settings.workdir);
+        let history = match history::History::open(&settings.history_file, url) {
+            Ok(h) => h,
+            Err(e) => panic!("failed to load history for {}: {}", url, e),
+        };
+
+        let mut notifiers = notifier::NotifierRegistry::new();
+        if let Some((ref addr, ref smtp)) = settings.email {
+            notifiers.register(Box::new(email::EmailNotifier::new(addr, url, smtp.clone())));
+        }
+        if let Some(ref sc) = settings.slack {
+            notifiers.register(Box::new(slack::SlackNotifier::new(
+                &sc.hook_url,
+                &sc.channel,
+                url,
+                sc.verbose,
+                log_links.cloned(),
+            )));
+        }
+        if let Some(ref key) = settings.github_api_key {
+            notifiers.register(Box::new(github::GithubNotifier::new(
+                key,
+                url,
+                log_links.cloned(),
+            )));
+        }
+        if let Some(ref wc) = settings.webhook {
+            notifiers.register(Box::new(webhook::WebhookNotifier::new(
+                &wc.urls.iter().map(|u| u.as_str()).collect::<Vec<_>>(),
+                wc.secret.as_ref().map(|s| s.as_str()),
+                url,
+            )));
+        }
+
+        RepoState {
+            ws: Mutex::new(ws),
+            history: Mutex::new(history),
+            notifiers: Arc::new(notifiers),
+            settings: settings,
+        }
+    }
+}
+
+/// Queues a tasting of `commits` in the repository at `repo`, notifying that any jobs dropped in
+/// its favour have been superseded. Commits are reported pending once a worker picks them up, so
+/// the webhook handler does not wait on the notifiers.
+fn enqueue(
+    queue: &queue::JobQueue,
+    repo: &str,
+    notifiers: &notifier::NotifierRegistry,
+    policy: queue::Supersede,
+    push: Push,
+    commits: Vec<Commit>,
+) {
+    let (id, superseded) = queue.enqueue(repo, push, commits, policy);
+    println!("Queued job {}", id);
+    for job in superseded {
+        println!("Dropped job {}, superseded by job {}", job.id, id);
+        if job.seed {
+            // nobody was told about it in the first place
+            continue;
+        }
+        for c in &job.commits {
+            notifiers.superseded(&job.push, c);
+        }
+    }
+}
+
 pub fn main() {
-    use clap::{App, Arg, ErrorKind};
+    use clap::{App, AppSettings, Arg, SubCommand};
 
     env_logger::init().unwrap();
 
     let args = App::new("taster")
         .version("0.0.1")
         .about("Tastes GitHub commits.")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .env("TASTER_CONFIG")
+                .help(
+                    "Server config file; settings given as flags or in the environment \
+                     override it",
+                ),
+        )
         .arg(
             Arg::with_name("listen_addr")
                 .short("l")
                 .long("listen_addr")
                 .takes_value(true)
                 .value_name("IP:PORT")
-                .default_value("0.0.0.0:4567")
-                .help("Listen address and port for webhook delivery"),
+                .env("TASTER_LISTEN_ADDR")
+                .help("Listen address and port for webhook delivery [default: 0.0.0.0:4567]"),
+        )
+        .arg(
+            Arg::with_name("status_addr")
+                .long("status_addr")
+                .takes_value(true)
+                .value_name("IP:PORT")
+                .env("TASTER_STATUS_ADDR")
+                .help(
+                    "Listen address and port for the job queue and log pages, which need no \
+                     authentication [default: not served]",
+                ),
+        )
+        .arg(
+            Arg::with_name("public_url")
+                .long("public_url")
+                .takes_value(true)
+                .value_name("URL")
+                .env("TASTER_PUBLIC_URL")
+                .help(
+                    "URL under which the status address is reachable; if set, notifications \
+                     link to the logs served there",
+                ),
         )
         .arg(
             Arg::with_name("github_repo")
                 .short("r")
                 .long("github_repo")
                 .takes_value(true)
-                .required(true)
                 .value_name("GH_REPO")
-                .default_value("https://github.com/ms705/taster")
-                .help("GitHub repository to taste"),
+                .env("TASTER_GITHUB_REPO")
+                .help("GitHub repository to taste [default: https://github.com/ms705/taster]"),
+        )
+        .arg(
+            Arg::with_name("bisect")
+                .long("bisect")
+                .required(false)
+                .help(
+                    "Bisect regressions relative to a baseline older than the regressed \
+                     commit's parent to find the culprit commit",
+                ),
         )
         .arg(
             Arg::with_name("email_addr")
                 .long("email_addr")
                 .takes_value(true)
                 .required(false)
+                .env("TASTER_EMAIL_ADDR")
                 .help("Email address to send notifications to"),
         )
+        .arg(
+            Arg::with_name("email_from")
+                .long("email_from")
+                .takes_value(true)
+                .env("TASTER_EMAIL_FROM")
+                .help("Sender address for email notifications [default: taster@localhost]"),
+        )
         .arg(
             Arg::with_name("default_regression_reporting_threshold")
                 .long("default_regression_reporting_threshold")
                 .takes_value(true)
-                .default_value("0.1")
+                .env("TASTER_DEFAULT_REGRESSION_REPORTING_THRESHOLD")
                 .help(
                     "Relative performance threshold below which a result is considered a \
-                     regression that needs reporting (0.1 = +/-10%).",
+                     regression that needs reporting (0.1 = +/-10%) [default: 0.1]",
                 ),
         )
         .arg(
             Arg::with_name("default_improvement_reporting_threshold")
                 .long("default_improvement_reporting_threshold")
                 .takes_value(true)
-                .default_value("0.1")
+                .env("TASTER_DEFAULT_IMPROVEMENT_REPORTING_THRESHOLD")
                 .help(
                     "Relative performance threshold above which a result is considered an \
-                     improvement that needs reporting (0.1 = +/-10%).",
+                     improvement that needs reporting (0.1 = +/-10%) [default: 0.1]",
+                ),
+        )
+        .arg(
+            Arg::with_name("history_file")
+                .long("history_file")
+                .takes_value(true)
+                .required(false)
+                .value_name("FILE")
+                .env("TASTER_HISTORY_FILE")
+                .help(
+                    "File in which to persist tasting results across restarts (defaults to \
+                     taster-history.jsonl in the workspace's .git directory)",
+                ),
+        )
+        .arg(
+            Arg::with_name("log_dir")
+                .long("log_dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .env("TASTER_LOG_DIR")
+                .help(
+                    "Directory to store the output of builds, tests, and benchmarks in \
+                     (defaults to taster-logs in the workspace's .git directory)",
+                ),
+        )
+        .arg(
+            Arg::with_name("log_retention_days")
+                .long("log_retention_days")
+                .takes_value(true)
+                .value_name("DAYS")
+                .env("TASTER_LOG_RETENTION_DAYS")
+                .help("Delete logs older than this many days"),
+        )
+        .arg(
+            Arg::with_name("log_max_size_mb")
+                .long("log_max_size_mb")
+                .takes_value(true)
+                .value_name("MB")
+                .env("TASTER_LOG_MAX_SIZE_MB")
+                .help("Delete the oldest logs once all logs take up more than this many MB"),
+        )
+        .arg(
+            Arg::with_name("log_compress_after_days")
+                .long("log_compress_after_days")
+                .takes_value(true)
+                .value_name("DAYS")
+                .env("TASTER_LOG_COMPRESS_AFTER_DAYS")
+                .help("Compress logs older than this many days"),
+        )
+        .arg(
+            Arg::with_name("queue_file")
+                .long("queue_file")
+                .takes_value(true)
+                .required(false)
+                .value_name("FILE")
+                .env("TASTER_QUEUE_FILE")
+                .help(
+                    "File in which to persist queued tasting jobs across restarts (defaults to \
+                     taster-queue.json in the workspace's .git directory)",
                 ),
         )
         .arg(
@@ -114,6 +332,8 @@ pub fn main() {
                 .long("secret")
                 .takes_value(true)
                 .required(false)
+                .env("TASTER_SECRET")
+                .hide_env_values(true)
                 .help("GitHub webhook secret"),
         )
         .arg(
@@ -121,6 +341,8 @@ pub fn main() {
                 .long("slack_hook_url")
                 .takes_value(true)
                 .required(false)
+                .env("TASTER_SLACK_HOOK_URL")
+                .hide_env_values(true)
                 .help("Slack webhook URL to push notifications to"),
         )
         .arg(
@@ -128,16 +350,172 @@ pub fn main() {
                 .long("slack_channel")
                 .takes_value(true)
                 .required(false)
-                .default_value("#soup-test")
-                .help("Slack channel for notifications"),
+                .env("TASTER_SLACK_CHANNEL")
+                .help("Slack channel for notifications [default: #soup-test]"),
         )
         .arg(
             Arg::with_name("github_api_key")
                 .long("github_api_key")
                 .takes_value(true)
                 .required(false)
+                .env("TASTER_GITHUB_API_KEY")
+                .hide_env_values(true)
                 .help("GitHub API key to provide status notifications"),
         )
+        .arg(
+            Arg::with_name("smtp_host")
+                .long("smtp_host")
+                .takes_value(true)
+                .env("TASTER_SMTP_HOST")
+                .help("SMTP server to send email notifications through [default: localhost]"),
+        )
+        .arg(
+            Arg::with_name("smtp_port")
+                .long("smtp_port")
+                .takes_value(true)
+                .env("TASTER_SMTP_PORT")
+                .help("SMTP server port [default: 25]"),
+        )
+        .arg(
+            Arg::with_name("smtp_user")
+                .long("smtp_user")
+                .takes_value(true)
+                .required(false)
+                .env("TASTER_SMTP_USER")
+                .help("Username for SMTP authentication"),
+        )
+        .arg(
+            Arg::with_name("smtp_password")
+                .long("smtp_password")
+                .takes_value(true)
+                .required(false)
+                .env("TASTER_SMTP_PASSWORD")
+                .hide_env_values(true)
+                .help("Password for SMTP authentication"),
+        )
+        .arg(
+            Arg::with_name("smtp_tls")
+                .long("smtp_tls")
+                .required(false)
+                .help("Require STARTTLS when talking to the SMTP server"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .short("j")
+                .long("concurrency")
+                .takes_value(true)
+                .value_name("N")
+                .env("TASTER_CONCURRENCY")
+                .help("Number of jobs to taste at the same time [default: 1]"),
+        )
+        .arg(
+            Arg::with_name("parallel_benchmarks")
+                .long("parallel_benchmarks")
+                .required(false)
+                .help(
+                    "Let benchmarks of concurrent jobs run at the same time, rather than one \
+                     after the other",
+                ),
+        )
+        .arg(
+            Arg::with_name("default_branch")
+                .long("default_branch")
+                .takes_value(true)
+                .value_name("BRANCH")
+                .env("TASTER_DEFAULT_BRANCH")
+                .help("Branch that new branches are compared against [default: master]"),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .long("branch")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("GLOB")
+                .env("TASTER_BRANCH")
+                .help(
+                    "Only taste pushes to branches matching this pattern (may be given multiple \
+                     times) [default: *]",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude_branch")
+                .long("exclude_branch")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("GLOB")
+                .env("TASTER_EXCLUDE_BRANCH")
+                .help(
+                    "Don't taste pushes to branches matching this pattern (may be given \
+                     multiple times)",
+                ),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("tag")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("GLOB")
+                .env("TASTER_TAG")
+                .help(
+                    "Only taste pushed tags matching this pattern (may be given multiple times) \
+                     [default: *]",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude_tag")
+                .long("exclude_tag")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("GLOB")
+                .env("TASTER_EXCLUDE_TAG")
+                .help(
+                    "Don't taste pushed tags matching this pattern (may be given multiple \
+                     times)",
+                ),
+        )
+        .arg(
+            Arg::with_name("seed_branch")
+                .long("seed_branch")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("GLOB")
+                .env("TASTER_SEED_BRANCH")
+                .help(
+                    "Taste the heads of branches matching this pattern on startup, unless \
+                     already tasted (may be given multiple times) [default: the default branch]",
+                ),
+        )
+        .arg(
+            Arg::with_name("supersede_queued")
+                .long("supersede_queued")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("BRANCH_GLOB")
+                .env("TASTER_SUPERSEDE_QUEUED")
+                .help(
+                    "Drop queued jobs for branches matching this pattern when a newer push to \
+                     the same branch arrives (may be given multiple times)",
+                ),
+        )
+        .arg(
+            Arg::with_name("supersede_running")
+                .long("supersede_running")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("BRANCH_GLOB")
+                .env("TASTER_SUPERSEDE_RUNNING")
+                .help(
+                    "Like --supersede_queued, but also kill a running job for the branch \
+                     (may be given multiple times)",
+                ),
+        )
         .arg(
             Arg::with_name("taste_commit")
                 .long("taste_commit")
@@ -157,7 +535,35 @@ pub fn main() {
                 .long("timeout")
                 .required(false)
                 .takes_value(true)
-                .help("Timeout (in seconds) after which benchmarks should be killed"),
+                .env("TASTER_TIMEOUT")
+                .help(
+                    "Timeout (in seconds) after which tests and each benchmark run are killed, \
+                     unless overridden by --test_timeout or --bench_timeout",
+                ),
+        )
+        .arg(
+            Arg::with_name("build_timeout")
+                .long("build_timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .env("TASTER_BUILD_TIMEOUT")
+                .help("Timeout (in seconds) for 'cargo update' and the build"),
+        )
+        .arg(
+            Arg::with_name("test_timeout")
+                .long("test_timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .env("TASTER_TEST_TIMEOUT")
+                .help("Timeout (in seconds) for the tests"),
+        )
+        .arg(
+            Arg::with_name("bench_timeout")
+                .long("bench_timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .env("TASTER_BENCH_TIMEOUT")
+                .help("Timeout (in seconds) for each benchmark run"),
         )
         .arg(
             Arg::with_name("verbose_notifications")
@@ -168,69 +574,118 @@ pub fn main() {
                      significantly",
                 ),
         )
+        .arg(
+            Arg::with_name("webhook_url")
+                .long("webhook_url")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("URL")
+                .env("TASTER_WEBHOOK_URL")
+                .help("URL to POST a JSON report of each tasting to (may be given multiple times)"),
+        )
+        .arg(
+            Arg::with_name("webhook_secret")
+                .long("webhook_secret")
+                .takes_value(true)
+                .required(false)
+                .env("TASTER_WEBHOOK_SECRET")
+                .hide_env_values(true)
+                .help("Secret for signing JSON reports with HMAC-SHA256"),
+        )
         .arg(
             Arg::with_name("workdir")
                 .short("w")
                 .long("workdir")
                 .takes_value(true)
-                .required(true)
                 .value_name("REPO_DIR")
+                .env("TASTER_WORKDIR")
                 .help("Directory holding the workspace repo"),
         )
+        .arg(
+            Arg::with_name("worktree_dir")
+                .long("worktree_dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .env("TASTER_WORKTREE_DIR")
+                .help(
+                    "Directory holding the worktrees that jobs are tasted in [default: \
+                     <REPO_DIR>-worktrees]",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check-config")
+                .about("Validates a taster.toml without tasting anything")
+                .arg(
+                    Arg::with_name("config")
+                        .index(1)
+                        .value_name("TASTER_TOML")
+                        .default_value("taster.toml")
+                        .help("Path to the taster.toml to check"),
+                )
+                .arg(
+                    Arg::with_name("sample_output")
+                        .long("sample_output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Sample benchmark output to extract metrics from"),
+                ),
+        )
         .after_help(TASTER_USAGE)
         .get_matches();
 
-    let addr = args.value_of("listen_addr").unwrap();
-    let email_notification_addr = args.value_of("email_addr");
-    let repo = args.value_of("github_repo").unwrap();
-    let secret = args.value_of("secret");
-    let slack_hook_url = args.value_of("slack_hook_url");
-    let slack_channel = args.value_of("slack_channel");
-    let taste_commit = args.value_of("taste_commit");
-    let github_api_key = args.value_of("github_api_key");
-    let taste_head_only = args.is_present("taste_head_only");
-    let workdir = Path::new(args.value_of("workdir").unwrap());
-    let verbose_notify = args.is_present("verbose_notifications");
-    let improvement_threshold =
-        value_t_or_exit!(args, "default_improvement_reporting_threshold", f64);
-    let regression_threshold =
-        value_t_or_exit!(args, "default_regression_reporting_threshold", f64);
-    let timeout = match value_t!(args, "timeout", u64) {
-        Ok(timeout) => Some(timeout),
-        Err(e) => match e.kind {
-            ErrorKind::ArgumentNotFound => None,
-            _ => panic!("failed to parse timeout: {:?}", e),
-        },
-    };
+    if let Some(args) = args.subcommand_matches("check-config") {
+        let ok = check::check_config(
+            Path::new(args.value_of("config").unwrap()),
+            args.value_of("sample_output").map(Path::new),
+            settings::DEFAULT_THRESHOLD,
+            settings::DEFAULT_THRESHOLD,
+        );
+        std::process::exit(if ok { 0 } else { 1 });
+    }
 
-    let mut history = HashMap::new();
-    let ws = repo::Workspace::new(repo, workdir);
-    let en = if let Some(addr) = email_notification_addr {
-        Some(email::EmailNotifier::new(addr, repo))
-    } else {
-        None
-    };
-    let sn = if let Some(url) = slack_hook_url {
-        Some(slack::SlackNotifier::new(
-            url,
-            slack_channel.unwrap(),
-            repo,
-            verbose_notify,
-        ))
-    } else {
-        None
-    };
-    let gn = if let Some(key) = github_api_key {
-        Some(github::GithubNotifier::new(key))
-    } else {
-        None
+    let settings = match settings::Settings::load(&args) {
+        Ok(s) => s,
+        Err(errors) => {
+            for e in errors {
+                println!("ERROR: {}", e);
+            }
+            std::process::exit(1);
+        }
     };
 
-    if taste_commit.is_some() {
-        let cid = if let Some("HEAD") = taste_commit {
+    let addr = settings.listen_addr.as_str();
+    let bisect_regressions = settings.bisect;
+    let taste_commit = args.value_of("taste_commit");
+    let taste_head_only = settings.taste_head_only;
+    let supersede = settings.supersede_policy();
+    let timeouts = settings.timeouts;
+    let logs = Arc::new(settings.logs.clone());
+    let log_links = settings.public_url.as_ref().map(|u| logs::LogLinks {
+        base_url: u.clone(),
+    });
+    // benchmarks of concurrent jobs take turns, unless we're told not to bother
+    let bench_lock = Arc::new(Mutex::new(()));
+    let exclusive_benchmarks = !settings.parallel_benchmarks;
+
+    let repos = Arc::new(
+        settings
+            .repos
+            .iter()
+            .map(|r| RepoState::open(r.clone(), log_links.as_ref()))
+            .collect::<Vec<_>>(),
+    );
+
+    if let Some(taste_commit) = taste_commit {
+        if repos.len() > 1 {
+            panic!("--taste_commit can only be used with a single repository");
+        }
+        let r = &repos[0];
+        let ws = r.ws.lock().unwrap();
+        let cid = if taste_commit == "HEAD" {
             ws.repo.head().unwrap().target().unwrap().clone()
         } else {
-            git2::Oid::from_str(taste_commit.unwrap()).unwrap()
+            git2::Oid::from_str(taste_commit).unwrap()
         };
         match ws.repo.find_object(cid, None) {
             Err(e) => panic!(format!("{}", e.description())),
@@ -239,42 +694,34 @@ pub fn main() {
                 let hc = Commit {
                     id: cobj.id(),
                     msg: String::from(cobj.message().unwrap()),
-                    url: format!("{}/commit/{}", repo, cobj.id()),
+                    url: format!("{}/commit/{}", r.settings.url, cobj.id()),
                 };
                 // fake a push
                 let push = Push {
                     head_commit: hc,
                     push_ref: None,
+                    base_ref: None,
                     pusher: None,
                     owner_name: None,
                     repo_name: None,
                 };
                 let res = taste::taste_commit(
                     &ws,
-                    &mut history,
+                    &r.history,
                     &push,
                     &push.head_commit,
-                    improvement_threshold,
-                    regression_threshold,
-                    timeout,
+                    &r.settings.default_branch,
+                    r.settings.improvement_threshold,
+                    r.settings.regression_threshold,
+                    &timeouts,
+                    &logs,
+                    &process::CancelToken::new(),
+                    None,
                 );
                 match res {
                     Err(e) => println!("ERROR: failed to taste{}: {}", cid, e),
                     Ok((cfg, tr)) => {
-                        // email notification
-                        if en.is_some() {
-                            en.as_ref()
-                                .unwrap()
-                                .notify(cfg.as_ref(), &tr, &push)
-                                .unwrap();
-                        }
-                        // slack notification
-                        if sn.is_some() {
-                            sn.as_ref()
-                                .unwrap()
-                                .notify(cfg.as_ref(), &tr, &push)
-                                .unwrap();
-                        }
+                        r.notifiers.finished(cfg.as_ref(), &tr, &push, &push.head_commit);
                         // We're done
                         return;
                     }
@@ -284,186 +731,408 @@ pub fn main() {
     }
 
     // If we get here, we must be running in continuous mode
-    if let None = secret {
-        panic!("--secret must be set when in continuous webhook handler mode");
+    for r in repos.iter() {
+        if r.settings.secret.is_none() {
+            panic!(
+                "a webhook secret (--secret, TASTER_SECRET, or `secret` in the config file) \
+                 must be set for {} when in continuous webhook handler mode",
+                r.settings.url
+            );
+        }
     }
 
-    // Initialize history by tasting the HEAD commit of each branch
-    {
+    let queue = Arc::new(match queue::JobQueue::open(&settings.queue_file) {
+        Ok(q) => q,
+        Err(e) => panic!("failed to load job queue: {}", e),
+    });
+
+    // Initialize history by tasting the HEAD commit of each branch, unless we already have
+    // results for it from a previous run. The workers taste these quietly, so that we can start
+    // accepting webhooks right away.
+    for r in repos.iter() {
+        let ws = r.ws.lock().unwrap();
         let branches = ws.branch_heads();
         for (b, c) in branches.iter() {
-            if b != "origin/master" {
+            if !b.starts_with("origin/") || b == "origin/HEAD" {
+                continue;
+            }
+            let branch = &b["origin/".len()..];
+            if !r.settings.seed_branches.matches(branch) {
+                continue;
+            }
+            let push_ref = format!("refs/heads/{}", branch);
+            if r.history
+                .lock()
+                .unwrap()
+                .contains(Some(&push_ref), &c.id().to_string())
+            {
+                println!("already tasted HEAD of {}: {}, skipping", b, c.id());
                 continue;
             }
-            println!(
-                "tasting HEAD of {}: {} / {}",
-                b,
-                c.id(),
-                c.message().unwrap()
-            );
             let hc = Commit {
                 id: c.id(),
                 msg: String::from(c.message().unwrap()),
-                url: format!("{}/commit/{}", repo, c.id()),
+                url: format!("{}/commit/{}", r.settings.url, c.id()),
             };
             // fake a push
             let push = Push {
-                head_commit: hc,
-                push_ref: Some(b.clone()),
+                head_commit: hc.clone(),
+                push_ref: Some(push_ref),
+                base_ref: None,
                 pusher: None,
                 owner_name: None,
                 repo_name: None,
             };
-            let res = taste::taste_commit(
-                &ws,
-                &mut history,
-                &push,
-                &push.head_commit,
-                improvement_threshold,
-                regression_threshold,
-                timeout,
-            );
-            assert!(res.is_ok());
+            match queue.seed(&r.settings.url, push, hc) {
+                Some(id) => println!(
+                    "Queued job {} to taste HEAD of {} in {}: {} / {}",
+                    id,
+                    b,
+                    r.settings.url,
+                    c.id(),
+                    c.message().unwrap()
+                ),
+                None => println!("HEAD of {} is already queued: {}, skipping", b, c.id()),
+            }
         }
     }
 
-    let hl = Arc::new(Mutex::new(history));
-    let wsl = Mutex::new(ws);
-
+    // Webhook deliveries are routed to the repository named in their payload; each repository
+    // has its own handlers, so that they can use different secrets
+    let supersede = Arc::new(supersede);
     let mut hub = Hub::new();
-    hub.handle_authenticated("push", secret.unwrap(), move |delivery: &Delivery| {
-        match delivery.payload {
-            Event::Push {
-                ref _ref,
-                ref commits,
-                ref head_commit,
-                ref pusher,
-                ref repository,
-                ..
-            } => {
-                println!(
-                    "Handling {} commits pushed by {}",
-                    commits.len(),
-                    pusher.name
-                );
+    let mut webhooks = Vec::new();
+    for r in repos.iter() {
+        let secret = r.settings.secret.clone().unwrap();
+        let full_name = r.settings.full_name();
+        for event in &["pull_request", "push"] {
+            webhooks.push((String::from(*event), secret.clone()));
+        }
+        {
+            let hub_queue = queue.clone();
+            let notifiers = r.notifiers.clone();
+            let supersede = supersede.clone();
+            let url = r.settings.url.clone();
+            let full_name = full_name.clone();
+            let branches = r.settings.branches.clone();
+            hub.handle_authenticated("pull_request", secret.as_str(), move |delivery: &Delivery| {
+                match delivery.payload {
+                    Event::PullRequest {
+                        ref action,
+                        ref number,
+                        ref pull_request,
+                        ref repository,
+                        ref sender,
+                        ..
+                    } => {
+                        if repository.full_name != full_name {
+                            return;
+                        }
+                        if action != "opened" && action != "synchronize" && action != "reopened"
+                        {
+                            return;
+                        }
+                        if !branches.matches(&pull_request.base._ref) {
+                            println!(
+                                "Ignoring pull request {}#{} against {}",
+                                full_name,
+                                number,
+                                pull_request.base._ref
+                            );
+                            return;
+                        }
+                        println!(
+                            "Handling {} pull request {}#{} by {}",
+                            action,
+                            full_name,
+                            number,
+                            sender.login
+                        );
 
-                // Data structures to represent info from webhook
-                let hc = Commit {
-                    id: git2::Oid::from_str(&head_commit.id).unwrap(),
-                    msg: head_commit.message.clone(),
-                    url: head_commit.url.clone(),
-                };
-                let push = Push {
-                    head_commit: hc,
-                    push_ref: Some(_ref.clone()),
-                    pusher: Some(pusher.name.clone()),
-                    owner_name: Some(repository.owner.name.clone()),
-                    repo_name: Some(repository.name.clone()),
-                };
+                        // PR heads may live in forks, so we taste them via the base repo's
+                        // refs/pull/<n>/head ref and compare against the PR's target branch
+                        let pr_ref = format!("refs/pull/{}/head", number);
+                        let hc = Commit {
+                            id: git2::Oid::from_str(&pull_request.head.sha).unwrap(),
+                            msg: pull_request.title.clone(),
+                            url: format!(
+                                "{}/commits/{}",
+                                pull_request.html_url,
+                                pull_request.head.sha
+                            ),
+                        };
+                        let push = Push {
+                            head_commit: hc.clone(),
+                            push_ref: Some(pr_ref.clone()),
+                            base_ref: Some(format!("refs/heads/{}", pull_request.base._ref)),
+                            pusher: Some(sender.login.clone()),
+                            owner_name: Some(repository.owner.login.clone()),
+                            repo_name: Some(repository.name.clone()),
+                        };
 
-                let notify_pending = |push: &Push, commit: &Commit| {
-                    // github status notification
-                    if gn.is_some() {
-                        match gn.as_ref().unwrap().notify_pending(&push, &commit) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                println!("failed to deliver GitHub status notification: {:?}", e)
-                            }
-                        }
+                        let policy = supersede.for_ref(&pr_ref);
+                        enqueue(&hub_queue, &url, &notifiers, policy, push, vec![hc]);
                     }
-                };
-
-                let notify = |cfg: Option<&Config>,
-                              res: &taste::TastingResult,
-                              push: &Push,
-                              commit: &Commit| {
-                    // email notification
-                    if en.is_some() {
-                        en.as_ref().unwrap().notify(cfg, &res, &push).unwrap();
+                    _ => (),
+                }
+            });
+        }
+        let hub_queue = queue.clone();
+        let notifiers = r.notifiers.clone();
+        let supersede = supersede.clone();
+        let url = r.settings.url.clone();
+        let repo_settings = r.settings.clone();
+        hub.handle_authenticated("push", secret.as_str(), move |delivery: &Delivery| {
+            match delivery.payload {
+                Event::Push {
+                    ref _ref,
+                    ref commits,
+                    ref head_commit,
+                    ref pusher,
+                    ref repository,
+                    ..
+                } => {
+                    if repository.full_name != full_name {
+                        return;
                     }
-                    // slack notification
-                    if sn.is_some() {
-                        sn.as_ref().unwrap().notify(cfg, &res, &push).unwrap();
+                    if !repo_settings.wants_ref(_ref) {
+                        println!("Ignoring push to {} in {}", _ref, full_name);
+                        return;
                     }
-                    // github status notification
-                    if gn.is_some() {
-                        gn.as_ref()
-                            .unwrap()
-                            .notify(cfg, &res, &push, &commit)
-                            .unwrap();
+                    println!(
+                        "Handling {} commits pushed to {} by {}",
+                        commits.len(),
+                        full_name,
+                        pusher.name
+                    );
+
+                    // Data structures to represent info from webhook
+                    let hc = Commit {
+                        id: git2::Oid::from_str(&head_commit.id).unwrap(),
+                        msg: head_commit.message.clone(),
+                        url: head_commit.url.clone(),
+                    };
+                    let push = Push {
+                        head_commit: hc,
+                        push_ref: Some(_ref.clone()),
+                        base_ref: None,
+                        pusher: Some(pusher.name.clone()),
+                        owner_name: Some(repository.owner.name.clone()),
+                        repo_name: Some(repository.name.clone()),
+                    };
+
+                    // Taste the head commit first, followed by the others if needed
+                    let mut to_taste = vec![push.head_commit.clone()];
+                    if !taste_head_only {
+                        for c in commits.iter() {
+                            if c.id == head_commit.id {
+                                // skip HEAD as we've already queued it
+                                continue;
+                            }
+                            to_taste.push(Commit {
+                                id: git2::Oid::from_str(&c.id).unwrap(),
+                                msg: c.message.clone(),
+                                url: c.url.clone(),
+                            });
+                        }
+                    } else if !commits.is_empty() {
+                        println!(
+                            "Skipping {} remaining commits in push!",
+                            commits.len() - 1
+                        );
                     }
-                };
 
+                    let policy = supersede.for_ref(_ref);
+                    enqueue(&hub_queue, &url, &notifiers, policy, push, to_taste);
+                }
+                _ => (),
+            }
+        });
+    }
+
+    // Tasting happens on worker threads that drain the job queue, so that webhook deliveries can
+    // be acknowledged without waiting for builds and benchmarks to finish. Each worker tastes in
+    // its own worktree of each repository, so that workers don't trample on each other's
+    // checkouts.
+    for worker in 0..settings.concurrency {
+        let queue = queue.clone();
+        let repos = repos.clone();
+        let bench_lock = bench_lock.clone();
+        let logs = logs.clone();
+        thread::spawn(move || {
+            let bench_lock = if exclusive_benchmarks {
+                Some(&*bench_lock)
+            } else {
+                None
+            };
+            // worktrees are created the first time the worker tastes a repository
+            let mut worktrees: HashMap<usize, repo::Workspace> = HashMap::new();
+            let quiet = notifier::NotifierRegistry::new();
+            loop {
+                let (job, cancel) = queue.next();
+                // jobs queued before we supported multiple repositories don't name theirs
+                let ri = match repos
+                    .iter()
+                    .position(|r| r.settings.url == job.repo || job.repo.is_empty())
                 {
-                    notify_pending(&push, &push.head_commit);
-                    let ws = wsl.lock().unwrap();
-                    let mut history = hl.lock().unwrap();
-                    // First taste the head commit
-                    ws.fetch().unwrap();
-                    let head_res = taste::taste_commit(
-                        &ws,
-                        &mut history,
-                        &push,
-                        &push.head_commit,
-                        improvement_threshold,
-                        regression_threshold,
-                        timeout,
+                    Some(i) => i,
+                    None => {
+                        println!(
+                            "ERROR: dropping job {} for unknown repository {}",
+                            job.id,
+                            job.repo
+                        );
+                        queue.finish(job.id);
+                        continue;
+                    }
+                };
+                let r = &repos[ri];
+                let notifiers = if job.seed { &quiet } else { &*r.notifiers };
+                // a panic while tasting fails the job, rather than taking the worker down with it
+                let mut current = 0;
+                let ran = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    println!(
+                        "Worker {} running job {}: tasting {} commits in {}",
+                        worker,
+                        job.id,
+                        job.commits.len(),
+                        r.settings.url
                     );
-                    match head_res {
-                        Err(e) => println!(
-                            "ERROR: failed to taste HEAD commit {}: {}",
-                            head_commit.id,
-                            e
-                        ),
-                        Ok((cfg, tr)) => {
-                            notify(cfg.as_ref(), &tr, &push, &push.head_commit);
-                            // Taste others if needed
-                            if !taste_head_only {
-                                for c in commits.iter() {
-                                    if c.id == head_commit.id {
-                                        // skip HEAD as we've already tested it
-                                        continue;
-                                    }
-                                    let cur_c = Commit {
-                                        id: git2::Oid::from_str(&c.id).unwrap(),
-                                        msg: c.message.clone(),
-                                        url: c.url.clone(),
-                                    };
-                                    notify_pending(&push, &cur_c);
-                                    // taste
-                                    let res = taste::taste_commit(
-                                        &ws,
-                                        &mut history,
-                                        &push,
-                                        &cur_c,
-                                        improvement_threshold,
-                                        regression_threshold,
-                                        timeout,
+                    let prepared = {
+                        // fetches go through the main checkout, whose refs the worktrees share
+                        let ws = r.ws.lock().unwrap();
+                        let fetched = match job.push.push_ref {
+                            Some(ref pr) if pr.starts_with("refs/pull/") => {
+                                ws.fetch().and_then(|_| {
+                                    ws.fetch_refs(&[format!("+{}:{}", pr, pr).as_str()])
+                                })
+                            }
+                            _ => ws.fetch(),
+                        };
+                        match fetched {
+                            Err(e) => Err(format!("failed to fetch: {}", e)),
+                            Ok(()) if worktrees.contains_key(&ri) => Ok(()),
+                            Ok(()) => {
+                                let name = format!("taster-worker-{}", worker);
+                                let path = r.settings.worktree_dir.join(&name);
+                                ws.worktree(&name, &path).map(|wt| {
+                                    worktrees.insert(ri, wt);
+                                })
+                            }
+                        }
+                    };
+                    if let Err(e) = prepared {
+                        println!("ERROR: failed to prepare job {}: {}", job.id, e);
+                        for c in &job.commits {
+                            notifiers.error(&job.push, c, &e);
+                        }
+                        return;
+                    }
+                    let wt = &worktrees[&ri];
+                    for (i, commit) in job.commits.iter().enumerate() {
+                        if cancel.is_cancelled() {
+                            println!("job {} was superseded by a newer push", job.id);
+                            for c in &job.commits[i..] {
+                                notifiers.superseded(&job.push, c);
+                            }
+                            break;
+                        }
+                        current = i;
+                        notifiers.pending(&job.push, commit);
+                        let res = taste::taste_commit(
+                            wt,
+                            &r.history,
+                            &job.push,
+                            commit,
+                            &r.settings.default_branch,
+                            r.settings.improvement_threshold,
+                            r.settings.regression_threshold,
+                            &timeouts,
+                            &logs,
+                            &cancel,
+                            bench_lock,
+                        );
+                        if cancel.is_cancelled() {
+                            println!("job {} was superseded by a newer push", job.id);
+                            for c in &job.commits[i..] {
+                                notifiers.superseded(&job.push, c);
+                            }
+                            break;
+                        }
+                        match res {
+                            Err(e) => {
+                                println!("ERROR: failed to taste commit {}: {}", commit.id, e);
+                                notifiers.error(&job.push, commit, &e);
+                                if i == 0 {
+                                    // don't bother with the rest of the push if the head failed
+                                    break;
+                                }
+                            }
+                            Ok((cfg, tr)) => {
+                                notifiers.finished(cfg.as_ref(), &tr, &job.push, commit);
+                                // the commit has been reported, so a panic while bisecting must
+                                // not report it as failed
+                                current = i + 1;
+                                if bisect_regressions && !job.seed && cfg.is_some() {
+                                    let culprits = bisect::bisect_regressions(
+                                        wt,
+                                        &r.history,
+                                        cfg.as_ref().unwrap(),
+                                        &tr,
+                                        &timeouts,
+                                        &logs,
+                                        job.push.push_ref.as_ref().map(|r| r.as_str()),
+                                        &cancel,
+                                        bench_lock,
                                     );
-                                    match res {
-                                        Err(e) => println!(
-                                            "ERROR: failed to taste commit {}: {}",
-                                            c.id,
-                                            e
-                                        ),
-                                        Ok((cfg, tr)) => notify(cfg.as_ref(), &tr, &push, &cur_c),
+                                    for culprit in culprits.iter() {
+                                        notifiers.culprit(cfg.as_ref(), culprit, &job.push);
                                     }
                                 }
-                            } else if !commits.is_empty() {
-                                println!(
-                                    "Skipping {} remaining commits in push!",
-                                    commits.len() - 1
-                                );
                             }
                         }
                     }
+                }));
+                if let Err(p) = ran {
+                    let msg = p.downcast_ref::<&str>()
+                        .map(|s| String::from(*s))
+                        .or_else(|| p.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| String::from("unknown cause"));
+                    let e = format!("Taster crashed: {}", msg);
+                    println!("ERROR: job {} panicked: {}", job.id, msg);
+                    for c in &job.commits[current..] {
+                        notifiers.error(&job.push, c, &e);
+                    }
                 }
+                queue.finish(job.id);
             }
-            _ => (),
-        }
+        });
+    }
+
+    // Old logs are cleaned up in the background
+    {
+        let logs = logs.clone();
+        thread::spawn(move || loop {
+            logs.prune();
+            thread::sleep(Duration::from_secs(60 * 60));
+        });
+    }
+
+    // The queue and the logs reveal commit messages and build output, so they are only served if
+    // asked for, and never on the address that has to be reachable by GitHub
+    let _status = settings.status_addr.as_ref().map(|a| {
+        let srvc = Server::http(&a[..]).unwrap().handle(server::StatusServer {
+            queue: queue,
+            logs: logs,
+        });
+        println!("Serving status pages on {}", a);
+        srvc.unwrap()
     });
 
-    let srvc = Server::http(&addr[..]).unwrap().handle(hub);
+    let srvc = Server::http(&addr[..]).unwrap().handle(server::TasterServer {
+        hub: hub,
+        webhooks: webhooks,
+    });
 
     println!("Taster listening on {}", addr);
     srvc.unwrap();
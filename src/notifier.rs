@@ -0,0 +1,114 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use bisect::Culprit;
+use config::Config;
+use taste::TastingResult;
+use Commit;
+use Push;
+
+/// A destination for tasting notifications. All hooks except `notify` default to doing nothing,
+/// so implementations only need to provide the events they care about.
+pub trait Notifier: Send + Sync {
+    /// Short name identifying the notifier in log messages.
+    fn name(&self) -> &str;
+
+    /// Called when `commit` has been queued for tasting.
+    fn notify_pending(&self, _push: &Push, _commit: &Commit) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when tasting `commit` has finished.
+    fn notify(
+        &self,
+        cfg: Option<&Config>,
+        res: &TastingResult,
+        push: &Push,
+        commit: &Commit,
+    ) -> Result<(), String>;
+
+    /// Called when `commit` could not be tasted at all.
+    fn notify_error(&self, _push: &Push, _commit: &Commit, _err: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when a queued or running tasting of `commit` was dropped in favour of a newer push.
+    fn notify_superseded(&self, _push: &Push, _commit: &Commit) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when bisection has identified the commit that introduced a regression.
+    fn notify_culprit(
+        &self,
+        _cfg: Option<&Config>,
+        _culprit: &Culprit,
+        _push: &Push,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Fans notifications out to any number of notifiers. A notifier that fails (or panics) is
+/// logged and skipped, so that it can't prevent the others from being notified.
+#[derive(Default)]
+pub struct NotifierRegistry {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl NotifierRegistry {
+    pub fn new() -> NotifierRegistry {
+        NotifierRegistry::default()
+    }
+
+    pub fn register(&mut self, notifier: Box<dyn Notifier>) {
+        println!("Registered {} notifier", notifier.name());
+        self.notifiers.push(notifier);
+    }
+
+    fn each<F>(&self, event: &str, f: F)
+    where
+        F: Fn(&dyn Notifier) -> Result<(), String>,
+    {
+        for n in self.notifiers.iter() {
+            match panic::catch_unwind(AssertUnwindSafe(|| f(&**n))) {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => println!(
+                    "failed to deliver {} {} notification: {}",
+                    n.name(),
+                    event,
+                    e
+                ),
+                Err(_) => println!(
+                    "{} notifier panicked while delivering {} notification",
+                    n.name(),
+                    event
+                ),
+            }
+        }
+    }
+
+    pub fn pending(&self, push: &Push, commit: &Commit) {
+        self.each("pending", |n| n.notify_pending(push, commit))
+    }
+
+    pub fn finished(
+        &self,
+        cfg: Option<&Config>,
+        res: &TastingResult,
+        push: &Push,
+        commit: &Commit,
+    ) {
+        self.each("result", |n| n.notify(cfg, res, push, commit))
+    }
+
+    pub fn error(&self, push: &Push, commit: &Commit, err: &str) {
+        self.each("error", |n| n.notify_error(push, commit, err))
+    }
+
+    pub fn superseded(&self, push: &Push, commit: &Commit) {
+        self.each("superseded", |n| n.notify_superseded(push, commit))
+    }
+
+    pub fn culprit(&self, cfg: Option<&Config>, culprit: &Culprit, push: &Push) {
+        self.each("culprit", |n| n.notify_culprit(cfg, culprit, push))
+    }
+}
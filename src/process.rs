@@ -0,0 +1,293 @@
+use libc;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often we check whether a running stage has exited or timed out.
+const POLL_INTERVAL_MS: u64 = 100;
+/// How long a stage that timed out gets to exit after SIGTERM before it is sent SIGKILL.
+const KILL_GRACE_SECS: u64 = 30;
+
+/// Handle through which the processes spawned for a job can be killed from another thread, e.g.
+/// when a newer push supersedes the job.
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+    /// Process group of the currently running child, if any.
+    pgid: Mutex<Option<libc::pid_t>>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Marks the job as cancelled and kills the process group of the running child, if any.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(pgid) = *self.pgid.lock().unwrap() {
+            println!("killing process group {}", pgid);
+            unsafe {
+                libc::killpg(pgid, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Wall-clock limits for each stage of a tasting; `None` means that the stage may run forever.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    /// Applies to `cargo update` and the build.
+    pub build: Option<Duration>,
+    pub test: Option<Duration>,
+    /// Applies to each run of a benchmark.
+    pub bench: Option<Duration>,
+}
+
+/// How a stage's process ended.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "detail")]
+pub enum Outcome {
+    /// Exited with status 0.
+    Success,
+    /// Exited with the given non-zero status.
+    Failed(i32),
+    /// Was killed by the given signal, other than because it timed out.
+    Signaled(i32),
+    /// Ran for longer than its timeout, and was killed.
+    TimedOut,
+    /// Could not be started at all, for the given reason.
+    SpawnError(String),
+}
+
+impl Outcome {
+    fn from_status(status: ExitStatus) -> Outcome {
+        match status.code() {
+            Some(0) => Outcome::Success,
+            Some(c) => Outcome::Failed(c),
+            None => Outcome::Signaled(status.signal().unwrap_or(0)),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        *self == Outcome::Success
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Success => write!(f, "succeeded"),
+            Outcome::Failed(c) => write!(f, "failed with exit code {}", c),
+            Outcome::Signaled(s) => write!(f, "was killed by signal {}", s),
+            Outcome::TimedOut => write!(f, "timed out"),
+            Outcome::SpawnError(ref e) => write!(f, "couldn't be started: {}", e),
+        }
+    }
+}
+
+/// A stage's process that has run to completion.
+#[derive(Debug)]
+pub struct Finished {
+    /// Program and arguments that were run.
+    pub command: Vec<String>,
+    /// Environment variables set for the process (on top of those it inherited).
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<PathBuf>,
+    pub outcome: Outcome,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub duration: Duration,
+}
+
+/// Files to which a stage's output is copied as it is produced, so that it can be followed while
+/// the stage runs.
+pub struct Tee {
+    pub stdout: File,
+    pub stderr: File,
+}
+
+/// Reads `pipe` to the end on a separate thread, so that a chatty child can't fill one pipe
+/// while we wait for it to exit. Everything read is also written to `copy`, if given.
+fn read_to_end<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    mut copy: Option<File>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut p) = pipe {
+            let mut chunk = [0; 8192];
+            loop {
+                let n = match p.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                buf.extend_from_slice(&chunk[..n]);
+                if let Some(ref mut f) = copy {
+                    let _ = f.write_all(&chunk[..n]);
+                }
+            }
+        }
+        buf
+    })
+}
+
+/// Waits for `child` to exit. Once `deadline` passes, its process group is sent SIGTERM, and
+/// SIGKILL if it still hasn't exited after a grace period. Returns whether it timed out.
+fn wait(
+    child: &mut Child,
+    pgid: libc::pid_t,
+    deadline: Option<Instant>,
+) -> io::Result<(ExitStatus, bool)> {
+    let mut kill_at = None;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, kill_at.is_some()));
+        }
+        let now = Instant::now();
+        match (deadline, kill_at) {
+            (Some(d), None) if now >= d => {
+                println!("process group {} timed out, terminating it", pgid);
+                unsafe {
+                    libc::killpg(pgid, libc::SIGTERM);
+                }
+                kill_at = Some(now + Duration::from_secs(KILL_GRACE_SECS));
+            }
+            (_, Some(k)) if now >= k => unsafe {
+                libc::killpg(pgid, libc::SIGKILL);
+            },
+            _ => (),
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+/// Runs `cmd` to completion in a new process group, capturing its output. The group is killed if
+/// `token` is cancelled or `timeout` expires, and also once `cmd` exits, so that it can't leave
+/// orphaned children (such as servers started by a benchmark) behind. Failing to start `cmd` is
+/// reported as its outcome; only cancellation (and failing to wait for `cmd`) yields an error.
+/// If `tee` is given, the output is also written to its files as it is produced.
+pub fn run(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+    tee: Option<Tee>,
+    token: &CancelToken,
+) -> io::Result<Finished> {
+    if token.is_cancelled() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "job was cancelled"));
+    }
+
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let command = iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+    let env = cmd.get_envs()
+        .filter_map(|(k, v)| {
+            v.map(|v| (k.to_string_lossy().into_owned(), v.to_string_lossy().into_owned()))
+        })
+        .collect();
+    let cwd = cmd.get_current_dir().map(PathBuf::from);
+
+    let start = Instant::now();
+    let mut child = match cmd.spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            return Ok(Finished {
+                command: command,
+                env: env,
+                cwd: cwd,
+                outcome: Outcome::SpawnError(e.to_string()),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                duration: start.elapsed(),
+            })
+        }
+    };
+    let pgid = child.id() as libc::pid_t;
+
+    *token.pgid.lock().unwrap() = Some(pgid);
+    // we may have been cancelled between the check above and registering the child
+    if token.is_cancelled() {
+        unsafe {
+            libc::killpg(pgid, libc::SIGKILL);
+        }
+    }
+    let (stdout_copy, stderr_copy) = match tee {
+        Some(t) => (Some(t.stdout), Some(t.stderr)),
+        None => (None, None),
+    };
+    let stdout = read_to_end(child.stdout.take(), stdout_copy);
+    let stderr = read_to_end(child.stderr.take(), stderr_copy);
+    let res = wait(&mut child, pgid, timeout.map(|t| start + t));
+    // take down whatever the stage left running in its process group
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+    *token.pgid.lock().unwrap() = None;
+    let (status, timed_out) = res?;
+
+    Ok(Finished {
+        command: command,
+        env: env,
+        cwd: cwd,
+        outcome: if timed_out {
+            Outcome::TimedOut
+        } else {
+            Outcome::from_status(status)
+        },
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+        duration: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_kills_process_group() {
+        // the backgrounded sleep is not the child we wait for, but is in its process group
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", "echo $$; sleep 100 & sleep 100"]);
+        let timeout = Duration::from_millis(300);
+        let finished = run(&mut cmd, Some(timeout), None, &CancelToken::new()).unwrap();
+        assert_eq!(finished.outcome, Outcome::TimedOut);
+        assert!(finished.duration >= timeout);
+        assert!(finished.duration < Duration::from_secs(KILL_GRACE_SECS));
+
+        let pgid: libc::pid_t = String::from_utf8_lossy(&finished.stdout).trim().parse().unwrap();
+        // orphans are reaped by init, which may take a moment
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while unsafe { libc::killpg(pgid, 0) } == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(unsafe { libc::killpg(pgid, 0) }, -1);
+        assert_eq!(io::Error::last_os_error().raw_os_error(), Some(libc::ESRCH));
+    }
+}
@@ -0,0 +1,456 @@
+use glob::Pattern;
+use serde_json;
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+use process::CancelToken;
+use Commit;
+use Push;
+
+/// What to do with existing jobs for a ref when a newer push to the same ref arrives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Supersede {
+    /// Taste every push.
+    Never,
+    /// Drop queued jobs for the ref, but let a running one finish.
+    Queued,
+    /// Drop queued jobs for the ref and cancel a running one.
+    Running,
+}
+
+/// Per-branch supersede policy, given as glob patterns over branch names.
+pub struct SupersedePolicy {
+    queued: Vec<Pattern>,
+    running: Vec<Pattern>,
+}
+
+impl SupersedePolicy {
+    pub fn new(queued: &[&str], running: &[&str]) -> Result<SupersedePolicy, String> {
+        let compile = |pats: &[&str]| {
+            pats.iter()
+                .map(|p| {
+                    Pattern::new(p).map_err(|e| format!("invalid branch pattern '{}': {}", p, e))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(SupersedePolicy {
+            queued: compile(queued)?,
+            running: compile(running)?,
+        })
+    }
+
+    /// Returns the policy for `push_ref`; patterns for cancelling running jobs take precedence.
+    pub fn for_ref(&self, push_ref: &str) -> Supersede {
+        let branch = if push_ref.starts_with("refs/heads/") {
+            &push_ref["refs/heads/".len()..]
+        } else {
+            push_ref
+        };
+        if self.running.iter().any(|p| p.matches(branch)) {
+            Supersede::Running
+        } else if self.queued.iter().any(|p| p.matches(branch)) {
+            Supersede::Queued
+        } else {
+            Supersede::Never
+        }
+    }
+}
+
+/// A pending tasting of one or more commits from a single push.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    /// URL of the repository the push went to.
+    #[serde(default)]
+    pub repo: String,
+    pub push: Push,
+    /// Commits to taste, in order; the head commit comes first.
+    pub commits: Vec<Commit>,
+    /// Whether the job tastes a branch head at startup to seed the history, in which case nobody
+    /// is notified of its results.
+    #[serde(default)]
+    pub seed: bool,
+}
+
+/// Accepts both a list of running jobs and the single (optional) running job that queue files
+/// written before we tasted in parallel contain.
+fn deserialize_running<'de, D>(d: D) -> Result<Vec<Job>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Running {
+        Many(Vec<Job>),
+        One(Option<Job>),
+    }
+    Ok(match Running::deserialize(d)? {
+        Running::Many(jobs) => jobs,
+        Running::One(job) => job.into_iter().collect(),
+    })
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct QueueState {
+    pub next_id: u64,
+    #[serde(default, deserialize_with = "deserialize_running")]
+    pub running: Vec<Job>,
+    pub pending: VecDeque<Job>,
+    /// Cancellation handles for the running jobs, by job ID.
+    #[serde(skip)]
+    cancel: HashMap<u64, Arc<CancelToken>>,
+}
+
+/// A queue of tasting jobs that is mirrored to disk on every change, so that jobs survive a
+/// restart of the server.
+pub struct JobQueue {
+    path: PathBuf,
+    state: Mutex<QueueState>,
+    cv: Condvar,
+}
+
+fn save(path: &Path, state: &QueueState) {
+    // write to a temporary file and rename it into place, so we never leave a truncated queue
+    let tmp = path.with_extension("tmp");
+    let res = serde_json::to_string_pretty(state)
+        .map_err(|e| format!("{}", e))
+        .and_then(|s| {
+            File::create(&tmp)
+                .and_then(|mut f| f.write_all(s.as_bytes()).and_then(|_| f.sync_data()))
+                .and_then(|_| fs::rename(&tmp, path))
+                .map_err(|e| format!("{}", e))
+        });
+    if let Err(e) = res {
+        println!("failed to persist job queue to {}: {}", path.display(), e);
+    }
+}
+
+impl JobQueue {
+    /// Opens the queue persisted at `path`. Jobs that were running when the server stopped are put
+    /// back at the front of the queue.
+    pub fn open(path: &Path) -> Result<JobQueue, String> {
+        let mut state = if path.exists() {
+            let mut buf = String::new();
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut buf))
+                .map_err(|e| format!("failed to read job queue {}: {}", path.display(), e))?;
+            serde_json::from_str::<QueueState>(&buf)
+                .map_err(|e| format!("failed to parse job queue {}: {}", path.display(), e))?
+        } else {
+            QueueState::default()
+        };
+        for job in state.running.drain(..).rev().collect::<Vec<_>>() {
+            println!("re-queueing job {} interrupted by restart", job.id);
+            state.pending.push_front(job);
+        }
+        if !state.pending.is_empty() {
+            println!("{} jobs pending from previous run", state.pending.len());
+        }
+        save(path, &state);
+
+        Ok(JobQueue {
+            path: path.to_path_buf(),
+            state: Mutex::new(state),
+            cv: Condvar::new(),
+        })
+    }
+
+    /// Adds a job for `commits` of `push` to `repo` to the back of the queue, and supersedes older
+    /// jobs for the same ref according to `policy`. Returns the new job's ID and any queued jobs
+    /// that were dropped; superseded running jobs are cancelled and left for their workers to
+    /// clean up.
+    pub fn enqueue(
+        &self,
+        repo: &str,
+        push: Push,
+        commits: Vec<Commit>,
+        policy: Supersede,
+    ) -> (u64, Vec<Job>) {
+        let mut state = self.state.lock().unwrap();
+
+        let same_ref = |j: &Job| j.repo == repo && j.push.push_ref == push.push_ref;
+        let mut superseded = Vec::new();
+        if policy != Supersede::Never && push.push_ref.is_some() {
+            let (stale, keep): (VecDeque<Job>, VecDeque<Job>) =
+                state.pending.drain(..).partition(|j| same_ref(j));
+            state.pending = keep;
+            superseded.extend(stale);
+
+            if policy == Supersede::Running {
+                for j in state.running.iter().filter(|j| same_ref(j)) {
+                    if let Some(cancel) = state.cancel.get(&j.id) {
+                        println!(
+                            "cancelling running job {} for {}",
+                            j.id,
+                            push.push_ref.as_ref().unwrap()
+                        );
+                        cancel.cancel();
+                    }
+                }
+            }
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending.push_back(Job {
+            id: id,
+            repo: String::from(repo),
+            push: push,
+            commits: commits,
+            seed: false,
+        });
+        save(&self.path, &state);
+        self.cv.notify_one();
+        (id, superseded)
+    }
+
+    /// Adds a job that seeds the history with `commit`, the head of `push` to `repo`, to the back
+    /// of the queue. Returns the new job's ID, or `None` if a job for the commit is already queued
+    /// (e.g., because the server restarted before tasting it).
+    pub fn seed(&self, repo: &str, push: Push, commit: Commit) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        let queued = state
+            .pending
+            .iter()
+            .chain(state.running.iter())
+            .any(|j| j.repo == repo && j.commits.iter().any(|c| c.id == commit.id));
+        if queued {
+            return None;
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending.push_back(Job {
+            id: id,
+            repo: String::from(repo),
+            push: push,
+            commits: vec![commit],
+            seed: true,
+        });
+        save(&self.path, &state);
+        self.cv.notify_one();
+        Some(id)
+    }
+
+    /// Blocks until a job is available, and marks it as running. The returned token is cancelled
+    /// if the job is superseded while it runs. Several workers may call this concurrently.
+    pub fn next(&self) -> (Job, Arc<CancelToken>) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.pending.pop_front() {
+                let cancel = Arc::new(CancelToken::new());
+                state.running.push(job.clone());
+                state.cancel.insert(job.id, cancel.clone());
+                save(&self.path, &state);
+                return (job, cancel);
+            }
+            state = self.cv.wait(state).unwrap();
+        }
+    }
+
+    /// Marks the running job `id` as done.
+    pub fn finish(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.running.retain(|j| j.id != id);
+        state.cancel.remove(&id);
+        save(&self.path, &state);
+    }
+
+    /// Serializes the current queue state as JSON.
+    pub fn to_json(&self) -> String {
+        let state = self.state.lock().unwrap();
+        serde_json::to_string_pretty(&*state).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2;
+    use std::env;
+    use std::process;
+
+    fn push(push_ref: Option<&str>, n: u8) -> (Push, Vec<Commit>) {
+        let c = Commit {
+            id: git2::Oid::from_bytes(&[n; 20]).unwrap(),
+            msg: format!("commit {}", n),
+            url: String::new(),
+        };
+        let p = Push {
+            head_commit: c.clone(),
+            push_ref: push_ref.map(String::from),
+            base_ref: None,
+            pusher: None,
+            owner_name: None,
+            repo_name: None,
+        };
+        (p, vec![c])
+    }
+
+    fn queue_file(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("taster-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("queue.json")
+    }
+
+    fn ids<'a, I: Iterator<Item = &'a Job>>(jobs: I) -> Vec<u64> {
+        jobs.map(|j| j.id).collect()
+    }
+
+    #[test]
+    fn survives_restarts() {
+        let path = queue_file("queue-restart");
+        let q = JobQueue::open(&path).unwrap();
+        for n in 0..3 {
+            let (p, c) = push(Some("refs/heads/master"), n);
+            let (id, superseded) = q.enqueue("repo", p, c, Supersede::Never);
+            assert_eq!(id, n as u64);
+            assert!(superseded.is_empty());
+        }
+        let (job, _) = q.next();
+        assert_eq!(job.id, 0);
+        assert_eq!(job.commits[0].msg, "commit 0");
+        let (job, _) = q.next();
+        q.finish(job.id);
+        drop(q);
+
+        // the job that was running when we stopped is tasted first after a restart
+        let q = JobQueue::open(&path).unwrap();
+        {
+            let state = q.state.lock().unwrap();
+            assert!(state.running.is_empty());
+            assert_eq!(ids(state.pending.iter()), vec![0, 2]);
+            assert_eq!(state.pending[0].repo, "repo");
+        }
+        let (p, c) = push(None, 3);
+        assert_eq!(q.enqueue("repo", p, c, Supersede::Never).0, 3);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reads_old_queue_files() {
+        let (p, c) = push(Some("refs/heads/master"), 7);
+        let job = serde_json::to_string(&Job {
+            id: 4,
+            repo: String::from("repo"),
+            push: p,
+            commits: c,
+            seed: false,
+        }).unwrap();
+        let path = queue_file("queue-compat");
+
+        // a single running job, from before jobs ran in parallel
+        let old = format!(r#"{{"next_id": 5, "running": {}, "pending": []}}"#, job);
+        for state in &[old.clone(), old.replace("\"repo\":\"repo\",", "")] {
+            fs::write(&path, state).unwrap();
+            let q = JobQueue::open(&path).unwrap();
+            let state = q.state.lock().unwrap();
+            assert_eq!(ids(state.pending.iter()), vec![4]);
+            assert_eq!(state.next_id, 5);
+        }
+
+        for running in &["null", "[]"] {
+            let state = format!(r#"{{"next_id": 5, "running": {}, "pending": []}}"#, running);
+            fs::write(&path, state).unwrap();
+            assert!(JobQueue::open(&path).unwrap().state.lock().unwrap().pending.is_empty());
+        }
+        fs::write(&path, r#"{"next_id": 5, "pending": []}"#).unwrap();
+        assert!(JobQueue::open(&path).is_ok());
+
+        fs::write(&path, "{").unwrap();
+        assert!(JobQueue::open(&path).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn policy_globs() {
+        let p = SupersedePolicy::new(&["feature/*", "master"], &["wip/*", "master"]).unwrap();
+        // cancelling running jobs takes precedence
+        assert_eq!(p.for_ref("refs/heads/master"), Supersede::Running);
+        assert_eq!(p.for_ref("refs/heads/wip/parser"), Supersede::Running);
+        assert_eq!(p.for_ref("refs/heads/feature/x"), Supersede::Queued);
+        assert_eq!(p.for_ref("refs/heads/feature"), Supersede::Never);
+        assert_eq!(p.for_ref("refs/heads/dev"), Supersede::Never);
+        // refs other than branches are matched in full
+        assert_eq!(p.for_ref("refs/tags/master"), Supersede::Never);
+        let p = SupersedePolicy::new(&["refs/pull/*"], &[]).unwrap();
+        assert_eq!(p.for_ref("refs/pull/12/head"), Supersede::Queued);
+
+        assert!(SupersedePolicy::new(&["[oops"], &[]).is_err());
+        assert!(SupersedePolicy::new(&[], &["[oops"]).is_err());
+    }
+
+    #[test]
+    fn superseding() {
+        let path = queue_file("queue-supersede");
+        let q = JobQueue::open(&path).unwrap();
+        let enqueue = |repo: &str, push_ref: Option<&str>, policy: Supersede| {
+            let (p, c) = push(push_ref, 0);
+            let (id, superseded) = q.enqueue(repo, p, c, policy);
+            (id, ids(superseded.iter()))
+        };
+        let pending = || ids(q.state.lock().unwrap().pending.iter());
+        let f = Some("refs/heads/f");
+
+        assert_eq!(enqueue("repo", f, Supersede::Never), (0, vec![]));
+        let (_, running) = q.next();
+        assert_eq!(enqueue("repo", f, Supersede::Never), (1, vec![]));
+        assert_eq!(enqueue("repo", f, Supersede::Never), (2, vec![]));
+        assert_eq!(pending(), vec![1, 2]);
+
+        // only queued jobs for the same ref of the same repo are dropped...
+        assert_eq!(enqueue("other", f, Supersede::Never), (3, vec![]));
+        assert_eq!(enqueue("repo", Some("refs/heads/g"), Supersede::Never), (4, vec![]));
+        assert_eq!(enqueue("repo", f, Supersede::Queued), (5, vec![1, 2]));
+        assert_eq!(pending(), vec![3, 4, 5]);
+        // ...and the running one is left alone
+        assert!(!running.is_cancelled());
+
+        assert_eq!(enqueue("repo", Some("refs/heads/g"), Supersede::Running), (6, vec![4]));
+        assert!(!running.is_cancelled());
+        assert_eq!(enqueue("repo", f, Supersede::Running), (7, vec![5]));
+        assert!(running.is_cancelled());
+        assert_eq!(pending(), vec![3, 6, 7]);
+
+        // pushes without a ref never supersede anything
+        assert_eq!(enqueue("repo", None, Supersede::Running), (8, vec![]));
+        assert_eq!(enqueue("repo", None, Supersede::Running), (9, vec![]));
+        assert_eq!(pending(), vec![3, 6, 7, 8, 9]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn seeding() {
+        let path = queue_file("queue-seed");
+        let q = JobQueue::open(&path).unwrap();
+        let (p, c) = push(Some("refs/heads/master"), 0);
+        assert_eq!(q.seed("repo", p.clone(), c[0].clone()), Some(0));
+        assert_eq!(q.seed("repo", p.clone(), c[0].clone()), None);
+        drop(q);
+
+        // a seed that was queued or running before a restart isn't queued again
+        let q = JobQueue::open(&path).unwrap();
+        let (job, _) = q.next();
+        assert!(job.seed);
+        assert_eq!(q.seed("repo", p.clone(), c[0].clone()), None);
+        assert_eq!(q.seed("other", p.clone(), c[0].clone()), Some(1));
+        q.finish(job.id);
+        assert_eq!(q.seed("repo", p.clone(), c[0].clone()), Some(2));
+
+        // nor is a commit that a push already queued
+        let (p, c) = push(Some("refs/heads/dev"), 1);
+        assert_eq!(q.enqueue("repo", p.clone(), c.clone(), Supersede::Never).0, 3);
+        assert!(!q.state.lock().unwrap().pending[2].seed);
+        assert_eq!(q.seed("repo", p, c[0].clone()), None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
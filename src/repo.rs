@@ -1,8 +1,11 @@
 use auth::with_authentication;
 
 use git2;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde::de::Error;
 use git2::{AutotagOption, BranchType, Commit, ErrorCode, FetchOptions, RemoteCallbacks,
-           Repository, ResetType};
+           Repository, ResetType, WorktreePruneOptions};
+use git2::build::CheckoutBuilder;
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
@@ -13,6 +16,32 @@ pub struct Workspace {
     pub remote_url: String,
 }
 
+/// Serializes a commit ID as its hex string, for use with `#[serde(serialize_with)]`.
+pub fn serialize_oid<S>(oid: &git2::Oid, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_str(&oid.to_string())
+}
+
+/// Counterpart to `serialize_oid`.
+pub fn deserialize_oid<'de, D>(d: D) -> Result<git2::Oid, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(d)?;
+    git2::Oid::from_str(&s).map_err(|e| D::Error::custom(e.message()))
+}
+
+/// Returns the `owner/name` of the GitHub repository at `url`.
+pub fn full_name(url: &str) -> String {
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = path.rsplitn(3, '/');
+    let name = parts.next().unwrap_or("");
+    let owner = parts.next().unwrap_or("");
+    format!("{}/{}", owner, name)
+}
+
 fn clone(url: &str, path: &Path) -> Result<Repository, git2::Error> {
     let cfg = git2::Config::new().unwrap();
 
@@ -79,8 +108,12 @@ impl Workspace {
     }
 
     pub fn fetch(&self) -> Result<(), git2::Error> {
-        let refspec = "+refs/heads/*:refs/remotes/origin/*";
+        self.fetch_refs(&["+refs/heads/*:refs/remotes/origin/*"])
+    }
 
+    /// Fetches `refspecs` from the remote, e.g. to get at `refs/pull/<n>/head` for a pull
+    /// request whose head lives in a fork.
+    pub fn fetch_refs(&self, refspecs: &[&str]) -> Result<(), git2::Error> {
         with_authentication(&self.remote_url, &try!(self.repo.config()), |f| {
             let mut cb = RemoteCallbacks::new();
             cb.credentials(f);
@@ -88,11 +121,55 @@ impl Workspace {
             let mut opts = FetchOptions::new();
             opts.remote_callbacks(cb).download_tags(AutotagOption::All);
 
-            try!(remote.fetch(&[refspec], Some(&mut opts), None));
+            try!(remote.fetch(refspecs, Some(&mut opts), None));
             Ok(())
         })
     }
 
+    /// Returns a workspace for the linked worktree `name` at `path`, creating it if needed. The
+    /// worktree shares this workspace's object database and refs, so commits fetched here can be
+    /// checked out there. A worktree that is no longer valid (e.g., because its directory was
+    /// removed) is pruned and recreated.
+    pub fn worktree(&self, name: &str, path: &Path) -> Result<Workspace, String> {
+        if let Ok(wt) = self.repo.find_worktree(name) {
+            if wt.validate().is_ok() {
+                let repo = Repository::open_from_worktree(&wt).map_err(|e| e.to_string())?;
+                return Ok(Workspace {
+                    path: String::from(path.to_str().unwrap()),
+                    repo: repo,
+                    remote_url: self.remote_url.clone(),
+                });
+            }
+            println!("Pruning stale worktree {} at {}", name, wt.path().display());
+            wt.prune(Some(WorktreePruneOptions::new().valid(true).working_tree(true)))
+                .map_err(|e| e.to_string())?;
+        }
+        // `git worktree add` creates a branch named after the worktree, which may be left over
+        // from a previous incarnation
+        if let Ok(mut b) = self.repo.find_branch(name, BranchType::Local) {
+            b.delete().map_err(|e| e.to_string())?;
+        }
+        if path.exists() {
+            fs::remove_dir_all(path)
+                .map_err(|e| format!("failed to remove {}: {}", path.display(), e))?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+        }
+
+        println!("Creating worktree {} at {}", name, path.display());
+        let wt = self.repo
+            .worktree(name, path, None)
+            .map_err(|e| format!("failed to create worktree {}: {}", name, e))?;
+        let repo = Repository::open_from_worktree(&wt).map_err(|e| e.to_string())?;
+        Ok(Workspace {
+            path: String::from(path.to_str().unwrap()),
+            repo: repo,
+            remote_url: self.remote_url.clone(),
+        })
+    }
+
     pub fn checkout_commit(&self, commit_id: &git2::Oid) -> Result<(), String> {
         use std::error::Error;
         // N.B.: this will turn into a no-op if the workdir contains the wrong
@@ -101,7 +178,11 @@ impl Workspace {
             Err(e) => return Err(String::from(e.description())),
             Ok(o) => o,
         };
-        match self.repo.reset(&c, ResetType::Hard, None) {
+        // also remove untracked files, so that a previous job can't leave anything behind (ignored
+        // files, such as build artifacts, are kept)
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force().remove_untracked(true);
+        match self.repo.reset(&c, ResetType::Hard, Some(&mut checkout)) {
             Ok(_) => println!("Checked out {}", commit_id),
             Err(e) => println!("Failed to check out {}: {}", commit_id, e.message()),
         };
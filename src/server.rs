@@ -0,0 +1,695 @@
+use afterparty::Hub;
+use hyper::buffer::BufReader;
+use hyper::header::{ContentLength, ContentType, Headers, TransferEncoding};
+use hyper::method::Method;
+use hyper::net::NetworkStream;
+use hyper::server::{Handler, Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use serde_json;
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use email::escape_html;
+use logs::{CommitLogs, LogStore};
+use queue::JobQueue;
+use webhook;
+
+/// Front-end for webhook deliveries, which it hands to the webhook hub.
+pub struct TasterServer {
+    pub hub: Hub,
+    /// The event and secret of each handler registered with `hub`, against which deliveries are
+    /// checked before they are handed to it.
+    pub webhooks: Vec<(String, String)>,
+}
+
+/// Serves the read-only status pages: the job queue and the stored logs. These reveal commit
+/// messages and build output, and need no authentication, so they are served on an address of
+/// their own rather than on the one that receives webhook deliveries.
+pub struct StatusServer {
+    pub queue: Arc<JobQueue>,
+    pub logs: Arc<LogStore>,
+}
+
+/// A response to a successful request for one of the log pages.
+struct Page {
+    content_type: ContentType,
+    /// Extra headers to send along.
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Page {
+    fn html(body: String) -> Page {
+        Page {
+            content_type: ContentType::html(),
+            headers: vec![],
+            body: body.into_bytes(),
+        }
+    }
+}
+
+type PageResult = Result<Page, (StatusCode, String)>;
+
+fn not_found<T>(what: &str) -> Result<T, (StatusCode, String)> {
+    Err((StatusCode::NotFound, format!("{} not found", what)))
+}
+
+fn internal_error(e: io::Error) -> (StatusCode, String) {
+    (StatusCode::InternalServerError, format!("{}", e))
+}
+
+/// Decodes the `%XX` escapes in a URL path segment; returns `None` if the result isn't UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s.as_bytes()[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            // `from_str_radix` would also accept a sign
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(s.as_bytes()[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Escapes a log directory or file name for use in a URL. The names only contain characters
+/// that are safe in URLs, apart from the `%` of their own escapes.
+fn url_component(name: &str) -> String {
+    name.replace('%', "%25")
+}
+
+/// Sends `msg` as the whole response, with status `status`.
+fn reply(mut res: Response, status: StatusCode, msg: &str) {
+    *res.status_mut() = status;
+    if let Err(e) = res.send(msg.as_bytes()) {
+        println!("failed to send response: {}", e);
+    }
+}
+
+/// Returns the value of the header `name`, if it is present and valid UTF-8.
+fn header(headers: &Headers, name: &str) -> Option<String> {
+    match headers.get_raw(name) {
+        Some(v) if v.len() == 1 => String::from_utf8(v[0].clone()).ok(),
+        _ => None,
+    }
+}
+
+/// Checks the `X-Hub-Signature-256` that GitHub sends along with a delivery of `body`, signed
+/// with `secret`.
+fn signed_with(secret: &str, body: &[u8], signature: &str) -> bool {
+    let expected = format!("sha256={}", webhook::sign(secret, body));
+    // compare in constant time so that the signature can't be guessed byte by byte
+    expected.len() == signature.len()
+        && expected
+            .bytes()
+            .zip(signature.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// A webhook delivery whose body has already been read, played back so that the hub can read it
+/// again.
+struct Replay {
+    data: Cursor<Vec<u8>>,
+    addr: SocketAddr,
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for Replay {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn ago(t: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(t).map(|d| d.as_secs()).unwrap_or(0);
+    if secs < 60 {
+        format!("{}s ago", secs)
+    } else if secs < 60 * 60 {
+        format!("{} min ago", secs / 60)
+    } else if secs < 24 * 60 * 60 {
+        format!("{} h ago", secs / (60 * 60))
+    } else {
+        format!("{} days ago", secs / (24 * 60 * 60))
+    }
+}
+
+fn page_start(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+         <style>td, th {{ padding: 2px 12px; text-align: left; }} \
+         pre {{ background: #f4f4f4; padding: 8px; white-space: pre-wrap; }}</style>\n\
+         </head>\n<body>\n<h1>{0}</h1>\n",
+        escape_html(title)
+    )
+}
+
+const PAGE_END: &'static str = "</body>\n</html>\n";
+
+/// Polls both of a stage's logs for new output, appending it to the page, until the stage has
+/// finished.
+const TAIL_SCRIPT: &'static str = r#"<script>
+function follow(stream, offset) {
+  fetch(base + "." + stream + ".log?offset=" + offset).then(function(r) {
+    var next = parseInt(r.headers.get("X-Log-Offset"), 10);
+    var complete = r.headers.get("X-Log-Complete") === "true";
+    return r.text().then(function(text) {
+      document.getElementById(stream).textContent += text;
+      if (!complete) {
+        setTimeout(function() { follow(stream, next); }, 1000);
+      } else if (stream === "stdout") {
+        document.getElementById("status").textContent = "Finished.";
+      }
+    });
+  });
+}
+follow("stdout", 0);
+follow("stderr", 0);
+</script>
+"#;
+
+impl StatusServer {
+    /// Serves the read-only log pages:
+    ///
+    ///  - `/logs`: the tastings whose logs are stored, most recent first
+    ///  - `/logs/<repo>/<branch>/<commit>`: the stages of a tasting
+    ///  - `.../<stage>`: a page that follows the stage's output while it runs
+    ///  - `.../<stage>.{stdout,stderr}.log?offset=<n>`: the stage's output from byte `n` on
+    ///  - `.../<stage>.json`: the stage's metadata, once it has finished
+    ///  - `.../<stage>.results/<file>`: a result file the stage wrote
+    fn serve_logs(&self, uri: &str, mut res: Response) {
+        let (path, query) = match uri.find('?') {
+            Some(i) => (&uri[..i], &uri[i + 1..]),
+            None => (uri, ""),
+        };
+        let parts = path["/logs".len()..]
+            .split('/')
+            .filter(|p| !p.is_empty())
+            .map(percent_decode)
+            .collect::<Option<Vec<_>>>();
+
+        let page = match parts {
+            None => not_found("log"),
+            Some(ref p) if p.is_empty() => self.log_index(),
+            Some(ref p) if p.len() >= 3 && p.len() <= 5 => {
+                match self.logs.find(&p[0], &p[1], &p[2]) {
+                    None => not_found("tasting"),
+                    Some(ref logs) if p.len() == 3 => commit_page(logs, path, &p[2]),
+                    Some(ref logs) if p.len() == 4 => stage_file(logs, path, &p[3], query),
+                    Some(ref logs) => result_file(logs, &p[3], &p[4]),
+                }
+            }
+            Some(_) => not_found("log"),
+        };
+
+        match page {
+            Ok(page) => {
+                res.headers_mut().set(page.content_type);
+                for (name, value) in page.headers {
+                    res.headers_mut().set_raw(name, vec![value.into_bytes()]);
+                }
+                if let Err(e) = res.send(&page.body) {
+                    println!("failed to send {}: {}", uri, e);
+                }
+            }
+            Err((status, msg)) => {
+                *res.status_mut() = status;
+                let _ = res.send(msg.as_bytes());
+            }
+        }
+    }
+
+    fn log_index(&self) -> PageResult {
+        let tastings = self.logs.tastings().map_err(internal_error)?;
+        let mut html = page_start("Tastings");
+        html.push_str(
+            "<table>\n<tr><th>Repository</th><th>Branch</th><th>Commit</th>\
+             <th>Last output</th></tr>\n",
+        );
+        for t in tastings {
+            let url = format!(
+                "/logs/{}/{}/{}",
+                url_component(&t.repo),
+                url_component(&t.branch),
+                url_component(&t.commit)
+            );
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td></tr>\n",
+                escape_html(&percent_decode(&t.repo).unwrap_or(t.repo.clone())),
+                escape_html(&percent_decode(&t.branch).unwrap_or(t.branch.clone())),
+                escape_html(&url),
+                escape_html(&t.commit),
+                ago(t.modified)
+            ));
+        }
+        html.push_str("</table>\n");
+        html.push_str(PAGE_END);
+        Ok(Page::html(html))
+    }
+}
+
+/// Lists the stages of the tasting of `commit` whose logs are in `logs`, which is served at
+/// `path`.
+fn commit_page(logs: &CommitLogs, path: &str, commit: &str) -> PageResult {
+    let stages = logs.stages().map_err(internal_error)?;
+    let base = path.trim_end_matches('/');
+    let mut html = page_start(&format!("Logs for {}", commit));
+    html.push_str(
+        "<table>\n<tr><th>Stage</th><th>Outcome</th><th>Duration</th><th>Logs</th></tr>\n",
+    );
+    for s in stages {
+        let url = escape_html(&format!("{}/{}", base, url_component(&s.name)));
+        let (outcome, duration) = match s.meta {
+            Some(ref m) => (format!("{}", m.outcome), format!("{:.1}s", m.duration_secs)),
+            None => (String::from("running"), String::new()),
+        };
+        let mut extra = String::new();
+        if s.meta.is_some() {
+            extra.push_str(&format!(" <a href=\"{}.json\">metadata</a>", url));
+        }
+        for f in logs.results(&s.name) {
+            extra.push_str(&format!(
+                " <a href=\"{}.results/{}\">{}</a>",
+                url,
+                escape_html(&url_component(&f)),
+                escape_html(&percent_decode(&f).unwrap_or(f.clone()))
+            ));
+        }
+        html.push_str(&format!(
+            "<tr><td><a href=\"{0}\">{1}</a></td><td>{2}</td><td>{3}</td>\
+             <td><a href=\"{0}.stdout.log\">stdout</a> <a href=\"{0}.stderr.log\">stderr</a>{4}\
+             </td></tr>\n",
+            url,
+            escape_html(&s.name),
+            escape_html(&outcome),
+            duration,
+            extra
+        ));
+    }
+    html.push_str("</table>\n");
+    html.push_str(PAGE_END);
+    Ok(Page::html(html))
+}
+
+/// Serves `file` from the tasting whose logs are in `logs` and are served at `path`: a stage's
+/// raw output or metadata, or the page following its output.
+fn stage_file(logs: &CommitLogs, path: &str, file: &str, query: &str) -> PageResult {
+    for &stream in &["stdout", "stderr"] {
+        let suffix = format!(".{}.log", stream);
+        if !file.ends_with(&suffix) {
+            continue;
+        }
+        let name = &file[..file.len() - suffix.len()];
+        let offset = query
+            .split('&')
+            .filter(|kv| kv.starts_with("offset="))
+            .filter_map(|kv| kv["offset=".len()..].parse::<u64>().ok())
+            .next()
+            .unwrap_or(0);
+        // check first, so that a complete log can't miss output written after we read it
+        let complete = logs.finished(name);
+        let data = match logs.read(name, stream, offset) {
+            Ok(d) => d,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return not_found("log"),
+            Err(e) => return Err(internal_error(e)),
+        };
+        return Ok(Page {
+            content_type: ContentType::plaintext(),
+            headers: vec![
+                ("X-Log-Offset", format!("{}", offset + data.len() as u64)),
+                ("X-Log-Complete", format!("{}", complete)),
+            ],
+            body: data,
+        });
+    }
+
+    if file.ends_with(".json") {
+        let meta = match logs.meta(&file[..file.len() - ".json".len()]) {
+            Some(m) => m,
+            None => return not_found("metadata"),
+        };
+        return Ok(Page {
+            content_type: ContentType::json(),
+            headers: vec![],
+            body: serde_json::to_vec_pretty(&meta).unwrap(),
+        });
+    }
+
+    let stages = logs.stages().map_err(internal_error)?;
+    let stage = match stages.iter().find(|s| s.name == file) {
+        Some(s) => s,
+        None => return not_found("stage"),
+    };
+    let mut html = page_start(&format!("Stage {}", stage.name));
+    if let Some(ref m) = stage.meta {
+        html.push_str(&format!(
+            "<p><code>{}</code> {} after {:.1}s.</p>\n",
+            escape_html(&m.command.join(" ")),
+            escape_html(&format!("{}", m.outcome)),
+            m.duration_secs
+        ));
+    }
+    let base = format!(
+        "{}/{}",
+        path.trim_end_matches('/').rsplitn(2, '/').nth(1).unwrap_or(""),
+        url_component(file)
+    );
+    // the path comes straight from the request, so it goes into the script as a JSON string, with
+    // `<` escaped so that it can't close the script element
+    let base = serde_json::to_string(&base)
+        .unwrap()
+        .replace('<', "\\u003c");
+    html.push_str(&format!(
+        "<p id=\"status\">Running...</p>\n<h2>stdout</h2>\n<pre id=\"stdout\"></pre>\n\
+         <h2>stderr</h2>\n<pre id=\"stderr\"></pre>\n\
+         <script>var base = {};</script>\n",
+        base
+    ));
+    html.push_str(TAIL_SCRIPT);
+    html.push_str(PAGE_END);
+    Ok(Page::html(html))
+}
+
+/// Serves the result file `file` archived in the directory `results` (`<stage>.results`) of the
+/// tasting whose logs are in `logs`.
+fn result_file(logs: &CommitLogs, results: &str, file: &str) -> PageResult {
+    if !results.ends_with(".results") {
+        return not_found("log");
+    }
+    match logs.read_result(&results[..results.len() - ".results".len()], file) {
+        Ok(data) => Ok(Page {
+            content_type: ContentType::plaintext(),
+            headers: vec![],
+            body: data,
+        }),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => not_found("result file"),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+impl TasterServer {
+    /// Hands a webhook delivery to the hub, if it is signed with the secret of one of the
+    /// handlers for its event. The hub silently drops deliveries that it can't authenticate or
+    /// that no handler wants, and answers them like any other, so they are checked here first to
+    /// make rejected deliveries show up as failed in GitHub's delivery log.
+    fn deliver(&self, mut req: Request, res: Response) {
+        let event = match header(&req.headers, "X-GitHub-Event") {
+            Some(e) => e,
+            None => return reply(res, StatusCode::BadRequest, "missing X-GitHub-Event header"),
+        };
+        let mut body = Vec::new();
+        if let Err(e) = req.read_to_end(&mut body) {
+            let msg = format!("failed to read delivery: {}", e);
+            return reply(res, StatusCode::BadRequest, &msg);
+        }
+
+        // deliveries of events we don't handle (such as the `ping` sent when a webhook is set up)
+        // only need to be signed with one of our secrets
+        let handled = self.webhooks.iter().any(|&(ref e, _)| *e == event);
+        let signature = header(&req.headers, "X-Hub-Signature-256").unwrap_or_default();
+        let authentic = self.webhooks
+            .iter()
+            .filter(|&&(ref e, _)| !handled || *e == event)
+            .any(|&(_, ref secret)| signed_with(secret, &body, &signature));
+        if !authentic {
+            println!("Rejecting {} delivery with a missing or invalid signature", event);
+            return reply(res, StatusCode::Forbidden, "missing or invalid signature");
+        }
+        if !handled {
+            return reply(res, StatusCode::Ok, &format!("ignoring {} event", event));
+        }
+
+        let mut headers = req.headers.clone();
+        headers.remove::<TransferEncoding>();
+        headers.set(ContentLength(body.len() as u64));
+        let mut data = format!("POST / HTTP/1.1\r\n{}\r\n", headers).into_bytes();
+        data.extend(body);
+        let mut stream = Replay {
+            data: Cursor::new(data),
+            addr: req.remote_addr,
+        };
+        let mut stream = BufReader::new(&mut stream as &mut dyn NetworkStream);
+        let replayed = match Request::new(&mut stream, req.remote_addr) {
+            Ok(r) => r,
+            Err(e) => {
+                let msg = format!("failed to replay delivery: {}", e);
+                return reply(res, StatusCode::InternalServerError, &msg);
+            }
+        };
+        // the hub's own answer is discarded: webhook deliveries are only queued, so tell GitHub
+        // we accepted them
+        let mut sink = io::sink();
+        let mut sink_headers = Headers::new();
+        self.hub
+            .handle(replayed, Response::new(&mut sink, &mut sink_headers));
+        reply(res, StatusCode::Accepted, "accepted");
+    }
+}
+
+impl Handler for TasterServer {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a>) {
+        match req.method {
+            Method::Post => self.deliver(req, res),
+            _ => reply(res, StatusCode::NotFound, "not found"),
+        }
+    }
+}
+
+impl Handler for StatusServer {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, mut res: Response<'a>) {
+        let path = match req.uri {
+            RequestUri::AbsolutePath(ref p) => Some(p.clone()),
+            _ => None,
+        };
+        match (&req.method, path) {
+            (&Method::Get, Some(ref p)) if p == "/queue" => {
+                res.headers_mut().set(ContentType::json());
+                if let Err(e) = res.send(self.queue.to_json().as_bytes()) {
+                    println!("failed to send queue state: {}", e);
+                }
+            }
+            (&Method::Get, Some(ref p)) if p == "/logs" || p.starts_with("/logs/") => {
+                self.serve_logs(p, res)
+            }
+            _ => reply(res, StatusCode::NotFound, "not found"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use process::{Finished, Outcome};
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    const PAYLOAD: &'static str = "{\"zen\": \"Keep it logically awesome.\"}";
+
+    fn server() -> TasterServer {
+        TasterServer {
+            hub: Hub::new(),
+            webhooks: vec![(String::from("push"), String::from("s3cret"))],
+        }
+    }
+
+    /// Sends a request carrying `PAYLOAD` to `srv`, and returns the response's status code and
+    /// body.
+    fn request<H: Handler>(srv: &H, method: &str, path: &str, headers: &[(&str, &str)]) -> String {
+        let mut data = format!("{} {} HTTP/1.1\r\n", method, path);
+        data.push_str(&format!("Content-Length: {}\r\n", PAYLOAD.len()));
+        for &(name, value) in headers {
+            data.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        data.push_str(&format!("\r\n{}", PAYLOAD));
+        let addr = "127.0.0.1:4567".parse().unwrap();
+        let mut stream = Replay {
+            data: Cursor::new(data.into_bytes()),
+            addr: addr,
+        };
+        let mut stream = BufReader::new(&mut stream as &mut dyn NetworkStream);
+        let req = Request::new(&mut stream, addr).unwrap();
+
+        let mut out = Vec::new();
+        {
+            let mut headers = Headers::new();
+            srv.handle(req, Response::new(&mut out, &mut headers));
+        }
+        let out = String::from_utf8(out).unwrap();
+        let status = out.split(' ').nth(1).unwrap();
+        let body = out.splitn(2, "\r\n\r\n").nth(1).unwrap();
+        format!("{} {}", status, body)
+    }
+
+    #[test]
+    fn deliveries_must_be_signed() {
+        let srv = server();
+        let good = format!("sha256={}", webhook::sign("s3cret", PAYLOAD.as_bytes()));
+        let bad = format!("sha256={}", webhook::sign("guess", PAYLOAD.as_bytes()));
+
+        let push = |sig: Option<&str>| {
+            let mut headers = vec![("X-GitHub-Event", "push")];
+            if let Some(sig) = sig {
+                headers.push(("X-Hub-Signature-256", sig));
+            }
+            request(&srv, "POST", "/", &headers)
+        };
+        assert_eq!(push(Some(&good)), "202 accepted");
+        assert_eq!(push(Some(&bad)), "403 missing or invalid signature");
+        assert_eq!(push(Some(&good[..good.len() - 1])), "403 missing or invalid signature");
+        assert_eq!(push(None), "403 missing or invalid signature");
+        assert_eq!(request(&srv, "POST", "/", &[]), "400 missing X-GitHub-Event header");
+
+        // events without handlers are acknowledged, but still need a valid signature
+        let ping = |sig: &str| {
+            request(
+                &srv,
+                "POST",
+                "/",
+                &[("X-GitHub-Event", "ping"), ("X-Hub-Signature-256", sig)],
+            )
+        };
+        assert_eq!(ping(&good), "200 ignoring ping event");
+        assert_eq!(ping(&bad), "403 missing or invalid signature");
+    }
+
+    #[test]
+    fn status_pages_are_separate() {
+        assert_eq!(request(&server(), "GET", "/queue", &[]), "404 not found");
+        assert_eq!(request(&server(), "GET", "/logs", &[]), "404 not found");
+
+        let dir = env::temp_dir().join(format!("taster-test-{}-status", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let status = StatusServer {
+            queue: Arc::new(JobQueue::open(&dir.join("queue.json")).unwrap()),
+            logs: Arc::new(LogStore {
+                dir: dir.join("logs"),
+                retention: None,
+                max_size: None,
+                compress_after: None,
+            }),
+        };
+        assert!(request(&status, "GET", "/queue", &[]).starts_with("200 {"));
+        assert!(request(&status, "GET", "/logs", &[]).starts_with("200 <!DOCTYPE html>"));
+        assert_eq!(request(&status, "POST", "/", &[]), "404 not found");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let decode = |s| percent_decode(s).unwrap();
+        assert_eq!(decode("master"), "master");
+        assert_eq!(decode("o%2Fr"), "o/r");
+        assert_eq!(decode("%2e%2E"), "..");
+        assert_eq!(decode("gr%C3%B6%C3%9Fe"), "größe");
+        assert_eq!(decode("o%252Fr"), "o%2Fr");
+        assert_eq!(decode(""), "");
+        for bad in &["%", "%4", "a%zz", "%+1", "%C3", "%C3%"] {
+            assert_eq!(percent_decode(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn log_paths_stay_inside_the_store() {
+        let dir = env::temp_dir().join(format!("taster-test-{}-log-paths", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secret.json"), "{\"secret\": 1}").unwrap();
+        let status = StatusServer {
+            queue: Arc::new(JobQueue::open(&dir.join("queue.json")).unwrap()),
+            logs: Arc::new(LogStore {
+                dir: dir.join("logs"),
+                retention: None,
+                max_size: None,
+                compress_after: None,
+            }),
+        };
+        let oid = ::git2::Oid::from_bytes(&[7; 20]).unwrap();
+        let commit = status
+            .logs
+            .for_commit("https://github.com/o/r", Some("refs/heads/f/x"), oid);
+        commit.tee("build").unwrap().stdout.write_all(b"compiling").unwrap();
+        commit.finish(
+            "build",
+            &Finished {
+                command: vec![String::from("cargo")],
+                env: vec![],
+                cwd: None,
+                outcome: Outcome::Success,
+                stdout: vec![],
+                stderr: vec![],
+                duration: Duration::from_secs(1),
+            },
+        );
+
+        let get = |path: &str| request(&status, "GET", path, &[]);
+        let base = format!("/logs/o%252Fr/f%252Fx/{}", oid);
+        assert!(get(&base).starts_with("200 <!DOCTYPE html>"));
+        assert_eq!(get(&format!("{}/build.stdout.log?offset=3", base)), "200 piling");
+        assert!(get(&format!("{}/build.json", base)).starts_with("200 {"));
+
+        for path in &[
+            // literal `..`
+            String::from("/logs/../secret.json"),
+            String::from("/logs/o%252Fr/../../secret.json"),
+            format!("{}/../../../../secret.json", base),
+            format!("{}/../build.json", base),
+            // encoded `..`, which isn't resolved before routing
+            String::from("/logs/%2e%2e/logs/%2e%2e/secret.json"),
+            String::from("/logs/%2E%2E/%2e%2e/%2e%2e/secret.json"),
+            format!("/logs/o%252Fr/%2e%2e/{}", oid),
+            format!("{}/%2e%2e%2f%2e%2e%2f%2e%2e%2f%2e%2e%2fsecret.json", base),
+            format!("{}/..%2fbuild.stdout.log", base),
+            format!("{}/build.results/..%2f..%2f..%2f..%2fsecret.json", base),
+            format!("{}/%2e%2e.results/secret.json", base),
+            // encoded `/`, which would join components
+            format!("/logs/o%2Fr/f%252Fx/{}", oid),
+            format!("/logs/o%252Fr%2Ff%252Fx/{}/build.json", oid),
+            String::from("/logs/%2F/%2F/%2Fsecret.json"),
+            format!("{}/%2Fbuild.json", base),
+            // backslashes and broken escapes
+            format!("{}/..%5c..%5csecret.json", base),
+            String::from("/logs/o%252Fr/f%252Fx/%zz"),
+        ] {
+            let res = get(path);
+            assert!(res.starts_with("404 "), "{}: {}", path, res);
+            assert!(!res.contains("secret"), "{}: {}", path, res);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,714 @@
+use clap::ArgMatches;
+use glob::Pattern;
+use std::fs::File;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use toml;
+
+use email::SmtpConfig;
+use logs::LogStore;
+use process::Timeouts;
+use queue::SupersedePolicy;
+use repo;
+
+pub const DEFAULT_LISTEN_ADDR: &'static str = "0.0.0.0:4567";
+pub const DEFAULT_GITHUB_REPO: &'static str = "https://github.com/ms705/taster";
+pub const DEFAULT_SLACK_CHANNEL: &'static str = "#soup-test";
+pub const DEFAULT_EMAIL_FROM: &'static str = "taster@localhost";
+pub const DEFAULT_SMTP_HOST: &'static str = "localhost";
+pub const DEFAULT_SMTP_PORT: u16 = 25;
+pub const DEFAULT_THRESHOLD: f64 = 0.1;
+pub const DEFAULT_BRANCH: &'static str = "master";
+
+/// Flags (and the environment variables named after them) that configure a single repository.
+/// With several repositories configured, it would be ambiguous which one they apply to, so they
+/// must then be set in each `[[repos]]` table instead.
+const REPO_FLAGS: &'static [&'static str] = &[
+    "github_repo",
+    "workdir",
+    "worktree_dir",
+    "history_file",
+    "secret",
+    "default_improvement_reporting_threshold",
+    "default_regression_reporting_threshold",
+    "default_branch",
+    "branch",
+    "exclude_branch",
+    "tag",
+    "exclude_tag",
+    "seed_branch",
+    "slack_hook_url",
+    "slack_channel",
+    "verbose_notifications",
+    "github_api_key",
+    "email_addr",
+    "email_from",
+    "smtp_host",
+    "smtp_port",
+    "smtp_user",
+    "smtp_password",
+    "smtp_tls",
+    "webhook_url",
+    "webhook_secret",
+];
+
+// The structs below mirror the server config file. Secrets can be given inline, or read from a
+// file named by the corresponding `*_file` key.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerFile {
+    listen_addr: Option<String>,
+    status_addr: Option<String>,
+    public_url: Option<String>,
+    queue_file: Option<PathBuf>,
+    timeout: Option<u64>,
+    build_timeout: Option<u64>,
+    test_timeout: Option<u64>,
+    bench_timeout: Option<u64>,
+    taste_head_only: Option<bool>,
+    bisect: Option<bool>,
+    supersede_queued: Option<Vec<String>>,
+    supersede_running: Option<Vec<String>>,
+    concurrency: Option<usize>,
+    parallel_benchmarks: Option<bool>,
+    logs: Option<LogsFile>,
+    /// A single repository.
+    repo: Option<RepoFile>,
+    /// Any number of repositories, as `[[repos]]` tables.
+    #[serde(default)]
+    repos: Vec<RepoFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogsFile {
+    dir: Option<PathBuf>,
+    retention_days: Option<u64>,
+    max_size_mb: Option<u64>,
+    compress_after_days: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RepoFile {
+    url: Option<String>,
+    workdir: Option<PathBuf>,
+    worktree_dir: Option<PathBuf>,
+    history_file: Option<PathBuf>,
+    secret: Option<String>,
+    secret_file: Option<PathBuf>,
+    improvement_threshold: Option<f64>,
+    regression_threshold: Option<f64>,
+    default_branch: Option<String>,
+    branches: Option<Vec<String>>,
+    exclude_branches: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    exclude_tags: Option<Vec<String>>,
+    seed_branches: Option<Vec<String>>,
+    slack: Option<SlackFile>,
+    github: Option<GithubFile>,
+    email: Option<EmailFile>,
+    webhook: Option<WebhookFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SlackFile {
+    hook_url: Option<String>,
+    hook_url_file: Option<PathBuf>,
+    channel: Option<String>,
+    verbose: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GithubFile {
+    api_key: Option<String>,
+    api_key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EmailFile {
+    to: Option<String>,
+    from: Option<String>,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_user: Option<String>,
+    smtp_password: Option<String>,
+    smtp_password_file: Option<PathBuf>,
+    smtp_tls: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WebhookFile {
+    urls: Option<Vec<String>>,
+    secret: Option<String>,
+    secret_file: Option<PathBuf>,
+}
+
+/// Selects names that match any of a set of glob patterns, but none of another.
+#[derive(Clone, Debug)]
+pub struct GlobFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl GlobFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<GlobFilter, String> {
+        let compile = |pats: &[String]| {
+            pats.iter()
+                .map(|p| Pattern::new(p).map_err(|e| format!("invalid pattern '{}': {}", p, e)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(GlobFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.include.iter().any(|p| p.matches(name))
+            && !self.exclude.iter().any(|p| p.matches(name))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SlackSettings {
+    pub hook_url: String,
+    pub channel: String,
+    pub verbose: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebhookSettings {
+    pub urls: Vec<String>,
+    pub secret: Option<String>,
+}
+
+/// Settings for one of the repositories that Taster tastes.
+#[derive(Clone, Debug)]
+pub struct RepoSettings {
+    pub url: String,
+    pub workdir: PathBuf,
+    /// Directory holding the worktrees in which jobs are tasted, one per worker.
+    pub worktree_dir: PathBuf,
+    pub history_file: PathBuf,
+    pub secret: Option<String>,
+    pub improvement_threshold: f64,
+    pub regression_threshold: f64,
+    /// Name of the branch that new branches are assumed to fork from.
+    pub default_branch: String,
+    /// Branches whose pushes (and pull requests against which) we taste.
+    pub branches: GlobFilter,
+    /// Tags whose pushes we taste.
+    pub tags: GlobFilter,
+    /// Branches whose heads we taste at startup.
+    pub seed_branches: GlobFilter,
+    pub slack: Option<SlackSettings>,
+    pub github_api_key: Option<String>,
+    /// Address to send notifications to, and how to send them.
+    pub email: Option<(String, SmtpConfig)>,
+    pub webhook: Option<WebhookSettings>,
+}
+
+/// Fully resolved server settings. Each value is taken from the command line if given there, from
+/// the environment if the corresponding `TASTER_*` variable is set, and from the config file
+/// otherwise, before falling back to a default.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub listen_addr: String,
+    /// Address on which the queue and the logs are served, if at all. They include commit
+    /// messages and build output, so they are not served on the webhook listen address.
+    pub status_addr: Option<String>,
+    /// URL under which the status address is reachable from outside, used to link to logs.
+    pub public_url: Option<String>,
+    pub queue_file: PathBuf,
+    pub timeouts: Timeouts,
+    pub taste_head_only: bool,
+    pub bisect: bool,
+    pub supersede_queued: Vec<String>,
+    pub supersede_running: Vec<String>,
+    /// Number of jobs tasted at the same time.
+    pub concurrency: usize,
+    /// Whether benchmarks of different jobs may run at the same time; if not, builds and tests
+    /// still run in parallel, but benchmarks take turns.
+    pub parallel_benchmarks: bool,
+    pub logs: LogStore,
+    pub repos: Vec<RepoSettings>,
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    let mut buf = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(buf)
+}
+
+/// Resolves a secret given either inline or as a file containing it; trailing whitespace (such
+/// as the newline most editors add) is ignored.
+fn secret(
+    name: &str,
+    inline: Option<String>,
+    file: Option<PathBuf>,
+    errors: &mut Vec<String>,
+) -> Option<String> {
+    match (inline, file) {
+        (Some(_), Some(_)) => {
+            errors.push(format!("only one of `{0}` and `{0}_file` may be set", name));
+            None
+        }
+        (Some(s), None) => Some(s),
+        (None, Some(f)) => match read_file(&f) {
+            Ok(s) => Some(String::from(s.trim_end())),
+            Err(e) => {
+                errors.push(format!("{}: {}", name, e));
+                None
+            }
+        },
+        (None, None) => None,
+    }
+}
+
+/// Returns the value of the argument `name` parsed as a `T`, if it was given.
+fn arg<T: FromStr>(args: &ArgMatches, name: &str, errors: &mut Vec<String>) -> Option<T> {
+    match args.value_of(name) {
+        None => None,
+        Some(v) => match v.parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                errors.push(format!("invalid value '{}' for --{}", v, name));
+                None
+            }
+        },
+    }
+}
+
+fn args_list(args: &ArgMatches, name: &str) -> Option<Vec<String>> {
+    args.values_of(name).map(|v| v.map(String::from).collect())
+}
+
+impl RepoSettings {
+    /// Resolves the settings for the repository described by `rf`, with command line arguments
+    /// taking precedence.
+    fn load(args: &ArgMatches, rf: RepoFile, errors: &mut Vec<String>) -> RepoSettings {
+        let workdir = match args.value_of("workdir").map(PathBuf::from).or(rf.workdir) {
+            Some(w) => w,
+            None => {
+                errors.push(String::from("no workdir given (use --workdir or `workdir`)"));
+                PathBuf::new()
+            }
+        };
+        if workdir.exists() && !workdir.is_dir() {
+            errors.push(format!("workdir {} is not a directory", workdir.display()));
+        }
+        // kept outside the workdir, so that build tools in the worktrees don't mistake the
+        // workdir for an enclosing project
+        let worktree_dir = args.value_of("worktree_dir")
+            .map(PathBuf::from)
+            .or(rf.worktree_dir)
+            .unwrap_or_else(|| {
+                let mut name = workdir.file_name().unwrap_or_default().to_os_string();
+                name.push("-worktrees");
+                workdir.with_file_name(name)
+            });
+        if worktree_dir.starts_with(&workdir) {
+            errors.push(format!(
+                "worktree_dir {} must not be inside the workdir",
+                worktree_dir.display()
+            ));
+        }
+
+        let threshold = |name: &str, file: Option<f64>, errors: &mut Vec<String>| {
+            let t = arg(args, name, errors).or(file).unwrap_or(DEFAULT_THRESHOLD);
+            if t < 0.0 {
+                errors.push(format!("{} must not be negative", name));
+            }
+            t
+        };
+        let improvement_threshold = threshold(
+            "default_improvement_reporting_threshold",
+            rf.improvement_threshold,
+            errors,
+        );
+        let regression_threshold = threshold(
+            "default_regression_reporting_threshold",
+            rf.regression_threshold,
+            errors,
+        );
+
+        let default_branch = args.value_of("default_branch")
+            .map(String::from)
+            .or(rf.default_branch)
+            .unwrap_or(String::from(DEFAULT_BRANCH));
+        let mut filter = |include: Vec<String>, exclude: Vec<String>| {
+            GlobFilter::new(&include, &exclude).unwrap_or_else(|e| {
+                errors.push(e);
+                GlobFilter::new(&[], &[]).unwrap()
+            })
+        };
+        let exclude_branches = args_list(args, "exclude_branch")
+            .or(rf.exclude_branches)
+            .unwrap_or(vec![]);
+        let branches = filter(
+            args_list(args, "branch")
+                .or(rf.branches)
+                .unwrap_or(vec![String::from("*")]),
+            exclude_branches.clone(),
+        );
+        let tags = filter(
+            args_list(args, "tag")
+                .or(rf.tags)
+                .unwrap_or(vec![String::from("*")]),
+            args_list(args, "exclude_tag")
+                .or(rf.exclude_tags)
+                .unwrap_or(vec![]),
+        );
+        let seed_branches = filter(
+            args_list(args, "seed_branch")
+                .or(rf.seed_branches)
+                .unwrap_or(vec![default_branch.clone()]),
+            exclude_branches,
+        );
+
+        let webhook_secret = {
+            let sf = secret("secret", rf.secret, rf.secret_file, errors);
+            args.value_of("secret").map(String::from).or(sf)
+        };
+
+        let slack = {
+            let sf = rf.slack.unwrap_or_default();
+            let hook_url = secret("slack.hook_url", sf.hook_url, sf.hook_url_file, errors);
+            match args.value_of("slack_hook_url").map(String::from).or(hook_url) {
+                None => None,
+                Some(url) => Some(SlackSettings {
+                    hook_url: url,
+                    channel: args.value_of("slack_channel")
+                        .map(String::from)
+                        .or(sf.channel)
+                        .unwrap_or(String::from(DEFAULT_SLACK_CHANNEL)),
+                    verbose: args.is_present("verbose_notifications")
+                        || sf.verbose.unwrap_or(false),
+                }),
+            }
+        };
+
+        let github_api_key = {
+            let gf = rf.github.unwrap_or_default();
+            let key = secret("github.api_key", gf.api_key, gf.api_key_file, errors);
+            args.value_of("github_api_key").map(String::from).or(key)
+        };
+
+        let email = {
+            let ef = rf.email.unwrap_or_default();
+            let password = secret(
+                "email.smtp_password",
+                ef.smtp_password,
+                ef.smtp_password_file,
+                errors,
+            );
+            let user = args.value_of("smtp_user").map(String::from).or(ef.smtp_user);
+            let password = args.value_of("smtp_password").map(String::from).or(password);
+            let credentials = match (user, password) {
+                (Some(u), Some(p)) => Some((u, p)),
+                (None, None) => None,
+                _ => {
+                    errors.push(String::from("SMTP user and password must be given together"));
+                    None
+                }
+            };
+            match args.value_of("email_addr").map(String::from).or(ef.to) {
+                None => None,
+                Some(to) => Some((
+                    to,
+                    SmtpConfig {
+                        host: args.value_of("smtp_host")
+                            .map(String::from)
+                            .or(ef.smtp_host)
+                            .unwrap_or(String::from(DEFAULT_SMTP_HOST)),
+                        port: arg(args, "smtp_port", errors)
+                            .or(ef.smtp_port)
+                            .unwrap_or(DEFAULT_SMTP_PORT),
+                        credentials: credentials,
+                        tls: args.is_present("smtp_tls") || ef.smtp_tls.unwrap_or(false),
+                        from: args.value_of("email_from")
+                            .map(String::from)
+                            .or(ef.from)
+                            .unwrap_or(String::from(DEFAULT_EMAIL_FROM)),
+                    },
+                )),
+            }
+        };
+
+        let webhook = {
+            let wf = rf.webhook.unwrap_or_default();
+            let secret = secret("webhook.secret", wf.secret, wf.secret_file, errors);
+            let secret = args.value_of("webhook_secret").map(String::from).or(secret);
+            match args_list(args, "webhook_url").or(wf.urls) {
+                Some(ref urls) if urls.is_empty() => None,
+                None => {
+                    if secret.is_some() {
+                        errors.push(String::from("a webhook secret requires a webhook URL"));
+                    }
+                    None
+                }
+                Some(urls) => Some(WebhookSettings {
+                    urls: urls,
+                    secret: secret,
+                }),
+            }
+        };
+
+        RepoSettings {
+            url: args.value_of("github_repo")
+                .map(String::from)
+                .or(rf.url)
+                .unwrap_or(String::from(DEFAULT_GITHUB_REPO)),
+            history_file: args.value_of("history_file")
+                .map(PathBuf::from)
+                .or(rf.history_file)
+                .unwrap_or(workdir.join(".git").join("taster-history.jsonl")),
+            workdir: workdir,
+            worktree_dir: worktree_dir,
+            secret: webhook_secret,
+            improvement_threshold: improvement_threshold,
+            regression_threshold: regression_threshold,
+            default_branch: default_branch,
+            branches: branches,
+            tags: tags,
+            seed_branches: seed_branches,
+            slack: slack,
+            github_api_key: github_api_key,
+            email: email,
+            webhook: webhook,
+        }
+    }
+
+    /// Whether pushes to `push_ref` should be tasted.
+    pub fn wants_ref(&self, push_ref: &str) -> bool {
+        if push_ref.starts_with("refs/heads/") {
+            self.branches.matches(&push_ref["refs/heads/".len()..])
+        } else if push_ref.starts_with("refs/tags/") {
+            self.tags.matches(&push_ref["refs/tags/".len()..])
+        } else {
+            true
+        }
+    }
+
+    /// The repository's `owner/name`, as GitHub reports it in webhook payloads.
+    pub fn full_name(&self) -> String {
+        repo::full_name(&self.url)
+    }
+}
+
+impl Settings {
+    /// Builds the settings from the config file named by `--config` (if any) and the command
+    /// line, and validates them. On failure, returns every problem found.
+    pub fn load(args: &ArgMatches) -> Result<Settings, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let file = match args.value_of("config") {
+            None => ServerFile::default(),
+            Some(path) => {
+                let parsed = read_file(Path::new(path)).and_then(|s| {
+                    toml::from_str::<ServerFile>(&s).map_err(|e| format!("{}: {}", path, e))
+                });
+                match parsed {
+                    Ok(f) => f,
+                    Err(e) => return Err(vec![e]),
+                }
+            }
+        };
+        let mut repo_files = file.repo.into_iter().chain(file.repos).collect::<Vec<_>>();
+        if repo_files.is_empty() {
+            // everything comes from the command line
+            repo_files.push(RepoFile::default());
+        }
+        let single = repo_files.len() == 1;
+        if !single {
+            for flag in REPO_FLAGS {
+                if args.is_present(flag) {
+                    errors.push(format!(
+                        "--{} can't be used with more than one repository configured; set it \
+                         in each `[[repos]]` table instead",
+                        flag
+                    ));
+                }
+            }
+        }
+        let repos = repo_files
+            .into_iter()
+            .enumerate()
+            .map(|(i, rf)| {
+                let mut repo_errors = Vec::new();
+                let repo = RepoSettings::load(args, rf, &mut repo_errors);
+                if single {
+                    errors.extend(repo_errors);
+                } else {
+                    let prefixed = repo_errors.into_iter().map(|e| format!("repos[{}]: {}", i, e));
+                    errors.extend(prefixed);
+                }
+                repo
+            })
+            .collect::<Vec<_>>();
+        for (i, a) in repos.iter().enumerate() {
+            for b in repos[i + 1..].iter() {
+                if a.full_name() == b.full_name() {
+                    errors.push(format!("{} is configured more than once", a.url));
+                }
+                if a.workdir == b.workdir {
+                    errors.push(format!(
+                        "{} and {} share the workdir {}",
+                        a.url,
+                        b.url,
+                        a.workdir.display()
+                    ));
+                }
+                if a.worktree_dir == b.worktree_dir {
+                    errors.push(format!(
+                        "{} and {} share the worktree_dir {}",
+                        a.url,
+                        b.url,
+                        a.worktree_dir.display()
+                    ));
+                }
+            }
+        }
+
+        let listen_addr = args.value_of("listen_addr")
+            .map(String::from)
+            .or(file.listen_addr)
+            .unwrap_or(String::from(DEFAULT_LISTEN_ADDR));
+        if listen_addr.parse::<SocketAddr>().is_err() {
+            errors.push(format!("invalid listen address '{}'", listen_addr));
+        }
+
+        let status_addr = args.value_of("status_addr")
+            .map(String::from)
+            .or(file.status_addr);
+        if let Some(ref a) = status_addr {
+            if a.parse::<SocketAddr>().is_err() {
+                errors.push(format!("invalid status address '{}'", a));
+            } else if *a == listen_addr {
+                errors.push(String::from("status address must differ from the listen address"));
+            }
+        }
+
+        let public_url = args.value_of("public_url")
+            .map(String::from)
+            .or(file.public_url)
+            .map(|u| String::from(u.trim_end_matches('/')));
+        if let Some(ref u) = public_url {
+            if !u.starts_with("http://") && !u.starts_with("https://") {
+                errors.push(format!("public URL '{}' must start with http:// or https://", u));
+            }
+            if status_addr.is_none() {
+                errors.push(String::from(
+                    "a public URL needs a status address to serve the logs it links to",
+                ));
+            }
+        }
+
+        // `timeout` is the default for the stages that run the code under test
+        let timeout = arg(args, "timeout", &mut errors).or(file.timeout);
+        let mut stage_timeout = |name: &str, file: Option<u64>, default: Option<u64>| {
+            let t = arg(args, name, &mut errors).or(file);
+            if t == Some(0) {
+                errors.push(format!("{} must be positive", name));
+            }
+            t.or(default).map(Duration::from_secs)
+        };
+        let timeouts = Timeouts {
+            build: stage_timeout("build_timeout", file.build_timeout, None),
+            test: stage_timeout("test_timeout", file.test_timeout, timeout),
+            bench: stage_timeout("bench_timeout", file.bench_timeout, timeout),
+        };
+        if timeout == Some(0) {
+            errors.push(String::from("timeout must be positive"));
+        }
+
+        let concurrency = arg(args, "concurrency", &mut errors)
+            .or(file.concurrency)
+            .unwrap_or(1);
+        if concurrency == 0 {
+            errors.push(String::from("concurrency must be at least 1"));
+        }
+
+        let logs = {
+            let lf = file.logs.unwrap_or_default();
+            let days = |d: u64| Duration::from_secs(d * 24 * 60 * 60);
+            LogStore {
+                dir: args.value_of("log_dir")
+                    .map(PathBuf::from)
+                    .or(lf.dir)
+                    .unwrap_or(repos[0].workdir.join(".git").join("taster-logs")),
+                retention: arg(args, "log_retention_days", &mut errors)
+                    .or(lf.retention_days)
+                    .map(days),
+                max_size: arg(args, "log_max_size_mb", &mut errors)
+                    .or(lf.max_size_mb)
+                    .map(|mb: u64| mb * 1024 * 1024),
+                compress_after: arg(args, "log_compress_after_days", &mut errors)
+                    .or(lf.compress_after_days)
+                    .map(days),
+            }
+        };
+        if logs.dir.exists() && !logs.dir.is_dir() {
+            errors.push(format!("log dir {} is not a directory", logs.dir.display()));
+        }
+
+        let supersede_queued = args_list(args, "supersede_queued")
+            .or(file.supersede_queued)
+            .unwrap_or(vec![]);
+        let supersede_running = args_list(args, "supersede_running")
+            .or(file.supersede_running)
+            .unwrap_or(vec![]);
+        if let Err(e) = SupersedePolicy::new(
+            &supersede_queued.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            &supersede_running.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+        ) {
+            errors.push(e);
+        }
+
+        let settings = Settings {
+            listen_addr: listen_addr,
+            status_addr: status_addr,
+            public_url: public_url,
+            queue_file: args.value_of("queue_file")
+                .map(PathBuf::from)
+                .or(file.queue_file)
+                .unwrap_or(repos[0].workdir.join(".git").join("taster-queue.json")),
+            timeouts: timeouts,
+            taste_head_only: args.is_present("taste_head_only")
+                || file.taste_head_only.unwrap_or(false),
+            bisect: args.is_present("bisect") || file.bisect.unwrap_or(false),
+            supersede_queued: supersede_queued,
+            supersede_running: supersede_running,
+            concurrency: concurrency,
+            parallel_benchmarks: args.is_present("parallel_benchmarks")
+                || file.parallel_benchmarks.unwrap_or(false),
+            logs: logs,
+            repos: repos,
+        };
+
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn supersede_policy(&self) -> SupersedePolicy {
+        // validated in `load`
+        SupersedePolicy::new(
+            &self.supersede_queued.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            &self.supersede_running.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+        ).unwrap()
+    }
+}
@@ -1,116 +1,125 @@
 use slack_hook::{Attachment, AttachmentBuilder, Field, PayloadBuilder, Slack, SlackLink, SlackText};
+use slack_hook::SlackTextContent;
 use slack_hook::SlackTextContent::{Link, Text};
 
+use Commit;
 use Push;
+use bisect::Culprit;
 use config::Config;
+use logs::LogLinks;
+use notifier::Notifier;
+use process::Outcome;
+use stats::Summary;
 use taste::{BenchmarkResult, TastingResult};
 
+/// Renders how a stage ended, with an icon that tells failures, crashes, and timeouts apart.
+fn outcome_text(outcome: &Outcome) -> String {
+    match *outcome {
+        Outcome::Success => String::from(":heavy_check_mark:"),
+        Outcome::Failed(c) => format!(":x: (exit code {})", c),
+        Outcome::Signaled(s) => format!(":boom: (killed by signal {})", s),
+        Outcome::TimedOut => String::from(":hourglass: (timed out)"),
+        Outcome::SpawnError(_) => String::from(":no_entry: (couldn't start)"),
+    }
+}
+
 pub struct SlackNotifier {
     conn: Slack,
     channel: String,
     verbose: bool,
+    repo_url: String,
+    /// Used to link each message to the commit's logs, if Taster is publicly reachable.
+    log_links: Option<LogLinks>,
 }
 
 impl SlackNotifier {
-    pub fn new(hook_url: &str, channel: &str, _repo_url: &str, verbose: bool) -> SlackNotifier {
+    pub fn new(
+        hook_url: &str,
+        channel: &str,
+        repo_url: &str,
+        verbose: bool,
+        log_links: Option<LogLinks>,
+    ) -> SlackNotifier {
         SlackNotifier {
             conn: Slack::new(hook_url).unwrap(),
             channel: String::from(channel),
             verbose: verbose,
+            repo_url: String::from(repo_url),
+            log_links: log_links,
         }
     }
 
-    pub fn notify(
-        &self,
-        cfg: Option<&Config>,
-        res: &TastingResult,
-        push: &Push,
-    ) -> Result<(), String> {
-        let mut text = vec![
-            Text("I've tasted _".into()),
-            Text(format!("\"{}\"_ (", push.head_commit.msg.lines().next().unwrap()).into()),
-            Link(SlackLink::new(
-                &push.head_commit.url,
-                &format!("{}", push.head_commit.id)[0..6],
-            )),
-        ];
-        match push.pusher {
-            Some(ref p) => {
-                let alias = match cfg {
-                    Some(cfg) => match cfg.slack_aliases.get(p) {
-                        None => p,
-                        Some(a) => a,
-                    },
-                    None => p,
-                };
-                text.push(Text(format!("), pushed by @{}", alias).into()))
-            }
-            None => text.push(Text(format!(")").into())),
-        }
-        match res.branch {
-            Some(ref b) => text.push(Text(format!("to *{}*", b).into())),
-            None => (),
-        }
-        let payload = PayloadBuilder::new()
-            .text(text.as_slice())
-            .attachments(self.result_to_attachments(&res))
-            .channel(self.channel.clone())
-            .username("taster")
-            .icon_emoji(":tea:")
-            .link_names(true)
-            .build()
-            .unwrap();
-
-        match self.conn.send(&payload) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("{:?}", e)),
+    /// Appends a link to the logs of `commit` tasted for `push` to `text`, if we serve them
+    /// publicly.
+    fn push_logs_link(&self, text: &mut Vec<SlackTextContent>, push: &Push, commit: &Commit) {
+        if let Some(ref links) = self.log_links {
+            let url = links.commit_url(
+                &self.repo_url,
+                push.push_ref.as_ref().map(|r| r.as_str()),
+                commit.id,
+            );
+            text.push(Text("(".into()));
+            text.push(Link(SlackLink::new(&url, "logs")));
+            text.push(Text(")".into()));
         }
     }
 
     fn result_to_attachments(&self, res: &TastingResult) -> Vec<Attachment> {
-        let color = if !res.build || !res.test || !res.bench {
+        let color = if !res.build.success() || !res.test.success() || !res.bench {
             "danger"
         } else {
             "good"
         };
 
-        let taste = if !res.build || !res.bench {
+        let taste = if !res.build.success() || !res.bench {
             "was inedible"
-        } else if !res.test {
+        } else if !res.test.success() {
             "had a mixed palate"
         } else {
             "tasted nice"
         };
 
-        let check = |title: &str, result: bool| {
-            let mut out = format!("{}: ", title);
-            if result {
-                out.push_str(":heavy_check_mark:");
-            } else {
-                out.push_str(":x:");
-            }
-            out
-        };
-
         let mut attachments = Vec::new();
         let build_att = AttachmentBuilder::new("")
             .title(format!("It {}.", taste))
             .text(format!(
-                "{} {} {}",
-                check("Build", res.build),
-                check("Tests", res.test),
-                check("Benchmarks", res.bench)
+                "Build: {} Tests: {} Benchmarks: {}",
+                outcome_text(&res.build),
+                outcome_text(&res.test),
+                if res.bench { ":heavy_check_mark:" } else { ":x:" }
             ))
             .color(color)
             .build()
             .unwrap();
         attachments.push(build_att);
 
-        let is_regression = |(_, v): (_, &BenchmarkResult<f64>)| match *v {
+        for &(stage, outcome) in [("Build", &res.build), ("Tests", &res.test)].iter() {
+            if let Outcome::SpawnError(ref e) = *outcome {
+                let att = AttachmentBuilder::new(e.as_str())
+                    .color("danger")
+                    .title(format!("{} couldn't be started", stage))
+                    .text(e.as_str())
+                    .build()
+                    .unwrap();
+                attachments.push(att);
+            }
+        }
+
+        if let Some(ref e) = res.config_error {
+            let att = AttachmentBuilder::new(e.as_str())
+                .color("danger")
+                .title("Invalid taster.toml")
+                .text(e.as_str())
+                .build()
+                .unwrap();
+            attachments.push(att);
+        }
+
+        let is_regression = |(_, v): (_, &BenchmarkResult<Summary>)| match *v {
             BenchmarkResult::Regression(_, _) => true,
             _ => false,
         };
-        let is_neutral = |(_, v): (_, &BenchmarkResult<f64>)| match *v {
+        let is_neutral = |(_, v): (_, &BenchmarkResult<Summary>)| match *v {
             BenchmarkResult::Neutral(_, _) => true,
             _ => false,
         };
@@ -120,9 +129,20 @@ impl SlackNotifier {
             Some(ref r) => {
                 for &(ref bm, ref status, ref res) in r {
                     if !status.success() {
+                        let title = match *status {
+                            Outcome::Failed(c) => format!("{} failed (exit code {})!", bm.name, c),
+                            Outcome::Signaled(s) => {
+                                format!("{} was killed by signal {}!", bm.name, s)
+                            }
+                            Outcome::TimedOut => format!("{} timed out!", bm.name),
+                            Outcome::SpawnError(ref e) => {
+                                format!("{} couldn't be started: {}", bm.name, e)
+                            }
+                            Outcome::Success => unreachable!(),
+                        };
                         let att = AttachmentBuilder::new("")
                             .color("danger")
-                            .title(format!("{} failed!", bm.name))
+                            .title(title)
                             .build()
                             .unwrap();
                         attachments.push(att);
@@ -182,3 +202,136 @@ impl SlackNotifier {
         attachments
     }
 }
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        "Slack"
+    }
+
+    fn notify(
+        &self,
+        cfg: Option<&Config>,
+        res: &TastingResult,
+        push: &Push,
+        commit: &Commit,
+    ) -> Result<(), String> {
+        let mut text = vec![
+            Text("I've tasted _".into()),
+            Text(format!("\"{}\"_ (", commit.msg.lines().next().unwrap_or("")).into()),
+            Link(SlackLink::new(&commit.url, &format!("{}", commit.id)[0..6])),
+        ];
+        match push.pusher {
+            Some(ref p) => {
+                let alias = match cfg {
+                    Some(cfg) => match cfg.slack_aliases.get(p) {
+                        None => p,
+                        Some(a) => a,
+                    },
+                    None => p,
+                };
+                text.push(Text(format!("), pushed by @{}", alias).into()))
+            }
+            None => text.push(Text(format!(")").into())),
+        }
+        match res.branch {
+            Some(ref b) => text.push(Text(format!("to *{}*", b).into())),
+            None => (),
+        }
+        if let Some(ref base) = push.base_ref {
+            let base = base.trim_start_matches("refs/heads/");
+            text.push(Text(format!("(compared to *{}*)", base).into()));
+        }
+        self.push_logs_link(&mut text, push, commit);
+        let payload = PayloadBuilder::new()
+            .text(text.as_slice())
+            .attachments(self.result_to_attachments(&res))
+            .channel(self.channel.clone())
+            .username("taster")
+            .icon_emoji(":tea:")
+            .link_names(true)
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+
+        match self.conn.send(&payload) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    fn notify_error(&self, push: &Push, commit: &Commit, err: &str) -> Result<(), String> {
+        let mut text = vec![
+            Text("I couldn't taste _".into()),
+            Text(format!("\"{}\"_ (", commit.msg.lines().next().unwrap_or("")).into()),
+            Link(SlackLink::new(&commit.url, &format!("{}", commit.id)[0..6])),
+            Text(")".into()),
+        ];
+        if let Some(ref r) = push.push_ref {
+            text.push(Text(format!("on *{}*", r.trim_start_matches("refs/heads/")).into()));
+        }
+        text.push(Text(format!(": {}", err).into()));
+        self.push_logs_link(&mut text, push, commit);
+
+        let payload = PayloadBuilder::new()
+            .text(text.as_slice())
+            .channel(self.channel.clone())
+            .username("taster")
+            .icon_emoji(":warning:")
+            .link_names(true)
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+
+        match self.conn.send(&payload) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    fn notify_culprit(
+        &self,
+        cfg: Option<&Config>,
+        culprit: &Culprit,
+        push: &Push,
+    ) -> Result<(), String> {
+        // git only knows the author's name and email, so culprits are looked up by the latter
+        let alias = match cfg {
+            Some(cfg) => match cfg.slack_aliases.get(&culprit.email) {
+                None => &culprit.author,
+                Some(a) => a,
+            },
+            None => &culprit.author,
+        };
+        let mut text = vec![
+            Text(format!("I've bisected the regression in *{}*", culprit.metric).into()),
+        ];
+        if let Some(ref r) = push.push_ref {
+            text.push(Text(format!("on *{}*", r.trim_start_matches("refs/heads/")).into()));
+        }
+        text.push(Text(
+            format!("to _\"{}\"_ (", culprit.commit.msg.lines().next().unwrap_or("")).into(),
+        ));
+        text.push(Link(SlackLink::new(
+            &culprit.commit.url,
+            &format!("{}", culprit.commit.id)[0..6],
+        )));
+        text.push(Text(format!("), authored by @{}", alias).into()));
+        match culprit.change {
+            Some(c) => text.push(Text(format!("({:+.2}%)", c * 100.0).into())),
+            None => text.push(Text("(and broke the benchmark)".into())),
+        }
+        self.push_logs_link(&mut text, push, &culprit.commit);
+
+        let payload = PayloadBuilder::new()
+            .text(text.as_slice())
+            .channel(self.channel.clone())
+            .username("taster")
+            .icon_emoji(":mag:")
+            .link_names(true)
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+
+        match self.conn.send(&payload) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use extract;
+
+/// Summary statistics over the samples collected for a metric across repeated benchmark runs.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub samples: Vec<f64>,
+    pub mean: f64,
+    pub stddev: f64,
+    /// 95% confidence interval for the mean; degenerate (`(mean, mean)`) for a single sample.
+    pub ci: (f64, f64),
+    pub unit: Option<String>,
+    /// Mean of the deviations that the benchmark itself reported for the samples, if any.
+    pub reported_deviation: Option<f64>,
+    /// Confidence interval for the mean that the benchmark itself reported (averaged over the
+    /// samples), if any.
+    pub reported_ci: Option<(f64, f64)>,
+}
+
+impl Summary {
+    pub fn new(samples: Vec<f64>) -> Summary {
+        assert!(!samples.is_empty());
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let stddev = if samples.len() > 1 {
+            (samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        let ci = if samples.len() > 1 {
+            let half = t_critical(0.05, n - 1.0) * stddev / n.sqrt();
+            (mean - half, mean + half)
+        } else {
+            (mean, mean)
+        };
+
+        Summary {
+            samples,
+            mean,
+            stddev,
+            ci,
+            unit: None,
+            reported_deviation: None,
+            reported_ci: None,
+        }
+    }
+
+    pub fn n(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // show values in a readable unit, e.g. ms rather than millions of ns
+        let (div, unit) = match self.unit {
+            Some(ref u) => {
+                let (div, unit) = extract::display_unit(self.mean, u);
+                (div, Some(unit))
+            }
+            None => (1.0, None),
+        };
+        if self.n() == 1 {
+            write!(f, "{:.2}", self.mean / div)?;
+            if let Some(d) = self.reported_deviation {
+                write!(f, " ± {:.2}", d / div)?;
+            }
+            if let Some((lo, hi)) = self.reported_ci {
+                write!(f, " [{:.2}, {:.2}]", lo / div, hi / div)?;
+            }
+        } else {
+            write!(
+                f,
+                "{:.2} ± {:.2}",
+                self.mean / div,
+                (self.ci.1 - self.ci.0) / 2.0 / div
+            )?;
+        }
+        if let Some(u) = unit {
+            write!(f, " {}", u)?;
+        }
+        if self.n() > 1 {
+            write!(f, " (n={})", self.n())?;
+        }
+        Ok(())
+    }
+}
+
+/// Welch's unequal-variances t-test. Returns the two-tailed p-value for the hypothesis that `a`
+/// and `b` have the same mean, or `None` if either side has fewer than two samples.
+pub fn welch_t_test(a: &Summary, b: &Summary) -> Option<f64> {
+    if a.n() < 2 || b.n() < 2 {
+        return None;
+    }
+    let (na, nb) = (a.n() as f64, b.n() as f64);
+    let va = a.stddev.powi(2) / na;
+    let vb = b.stddev.powi(2) / nb;
+    if va + vb == 0.0 {
+        // no variance at all: either the samples are identical, or they are certainly different
+        return Some(if a.mean == b.mean { 1.0 } else { 0.0 });
+    }
+    let t = (a.mean - b.mean) / (va + vb).sqrt();
+    let df = (va + vb).powi(2) / (va.powi(2) / (na - 1.0) + vb.powi(2) / (nb - 1.0));
+    Some(t_two_tailed_p(t, df))
+}
+
+/// Two-tailed p-value of `t` under Student's t distribution with `df` degrees of freedom.
+fn t_two_tailed_p(t: f64, df: f64) -> f64 {
+    inc_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// Critical value `t` such that the two-tailed p-value at `df` degrees of freedom is `alpha`.
+fn t_critical(alpha: f64, df: f64) -> f64 {
+    // p is monotonically decreasing in t, so bisect
+    let (mut lo, mut hi) = (0.0, 1000.0);
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if t_two_tailed_p(mid, df) > alpha {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// Natural logarithm of the gamma function for `x > 0` (Lanczos approximation).
+fn ln_gamma(x: f64) -> f64 {
+    const COEF: [f64; 9] = [
+        0.99999999999980993,
+        676.5203681218851,
+        -1259.1392167224028,
+        771.32342877765313,
+        -176.61502916214059,
+        12.507343278686905,
+        -0.13857109526572012,
+        9.9843695780195716e-6,
+        1.5056327351493116e-7,
+    ];
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let mut a = COEF[0];
+    for (i, c) in COEF.iter().enumerate().skip(1) {
+        a += c / (x + i as f64);
+    }
+    0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// Regularized incomplete beta function I_x(a, b).
+fn inc_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let bt = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        bt * beta_cf(a, b, x) / a
+    } else {
+        1.0 - bt * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction expansion for the incomplete beta function (modified Lentz's method).
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITER: usize = 300;
+    const EPS: f64 = 3.0e-14;
+    const FPMIN: f64 = 1.0e-300;
+
+    let clamp = |v: f64| if v.abs() < FPMIN { FPMIN } else { v };
+
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - qab * x / qap);
+    let mut h = d;
+    for m in 1..MAX_ITER + 1 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        // even step
+        let aa = m * (b - m) * x / ((qam + m2) * (a + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        h *= d * c;
+        // odd step
+        let aa = -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        let del = d * c;
+        h *= del;
+        if (del - 1.0).abs() < EPS {
+            break;
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, eps: f64) -> bool {
+        (a - b).abs() < eps
+    }
+
+    #[test]
+    fn inc_beta_matches_closed_form() {
+        // I_x(2, 3) = 6x^2(1-x)^2 + 4x^3(1-x) + x^4
+        let x: f64 = 0.3;
+        let expected = 6.0 * x.powi(2) * (1.0 - x).powi(2) + 4.0 * x.powi(3) * (1.0 - x)
+            + x.powi(4);
+        assert!(close(inc_beta(2.0, 3.0, x), expected, 1e-10));
+        assert_eq!(inc_beta(2.0, 3.0, 0.0), 0.0);
+        assert_eq!(inc_beta(2.0, 3.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn t_critical_matches_tables() {
+        assert!(close(t_critical(0.05, 10.0), 2.228, 1e-3));
+        assert!(close(t_critical(0.05, 1.0), 12.706, 1e-3));
+        assert!(close(t_critical(0.01, 20.0), 2.845, 1e-3));
+    }
+
+    #[test]
+    fn welch_t_test_matches_textbook_example() {
+        // Welch's t-test example from Wikipedia: t = -2.46, df = 25.0, p = 0.021
+        let a = Summary::new(vec![
+            27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7,
+            21.4,
+        ]);
+        let b = Summary::new(vec![
+            27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5,
+            24.4,
+        ]);
+        let p = welch_t_test(&a, &b).unwrap();
+        assert!(close(p, 0.021, 1e-3), "p = {}", p);
+        assert_eq!(welch_t_test(&a, &Summary::new(vec![1.0])), None);
+    }
+
+    #[test]
+    fn summary_of_samples() {
+        let s = Summary::new(vec![3.0, 1.0, 2.0, 4.0]);
+        assert_eq!(s.mean, 2.5);
+        assert!(close(s.stddev, 1.2910, 1e-4));
+        // mean ± t(0.05, 3) * stddev / sqrt(4), with t(0.05, 3) = 3.182
+        assert!(close(s.ci.1 - s.mean, 3.182 * 1.2910 / 2.0, 1e-3));
+    }
+
+    #[test]
+    fn display_in_readable_units() {
+        let mut s = Summary::new(vec![1007.0]);
+        s.unit = Some(String::from("ns/iter"));
+        s.reported_deviation = Some(12.0);
+        assert_eq!(s.to_string(), "1.01 ± 0.01 µs/iter");
+
+        // mean ± t(0.05, 1) * stddev / sqrt(2) = 3e6 ± 12.706 * 0.5e6
+        let mut s = Summary::new(vec![2.5e6, 3.5e6]);
+        s.unit = Some(String::from("ns"));
+        assert_eq!(s.to_string(), "3.00 ± 6.35 ms (n=2)");
+
+        let mut s = Summary::new(vec![999.0]);
+        s.unit = Some(String::from("ns"));
+        s.reported_ci = Some((990.0, 1010.0));
+        assert_eq!(s.to_string(), "999.00 [990.00, 1010.00] ns");
+
+        assert_eq!(Summary::new(vec![42.0]).to_string(), "42.00");
+    }
+}
@@ -1,14 +1,21 @@
-use config::{parse_config, Benchmark, Config};
+use config::{parse_config, Benchmark, Config, OutputFormat};
+use extract::{self, Measurement, MetricsFile};
 use git2;
+use history::{self, History, Tasting};
+use logs::{CommitLogs, LogStore};
+use process::{self, CancelToken, Finished, Outcome, Timeouts};
 use repo::Workspace;
+use stats::{self, Summary};
 use Commit;
 use Push;
 
+use std::cmp;
 use std::collections::HashMap;
 use std::io;
 use std::path::Path;
-use std::process::{Command, ExitStatus, Output};
-use std::str;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
 /// `(val, percentage_change)`
 #[derive(Debug, Clone)]
@@ -22,225 +29,488 @@ pub enum BenchmarkResult<T> {
 pub struct TastingResult {
     pub branch: Option<String>,
     pub commit: Commit,
-    pub build: bool,
-    pub test: bool,
+    /// How the build (including `cargo update`, if it ran) ended.
+    pub build: Outcome,
+    pub test: Outcome,
+    /// Whether all benchmarks succeeded; their individual outcomes are in `results`.
     pub bench: bool,
-    pub results: Option<Vec<(Benchmark, ExitStatus, HashMap<String, BenchmarkResult<f64>>)>>,
+    pub results: Option<Vec<(Benchmark, Outcome, HashMap<String, BenchmarkResult<Summary>>)>>,
+    /// Commit whose results each metric was compared against, and those results, by benchmark
+    /// and metric name. Metrics without a baseline are absent.
+    pub baselines: HashMap<String, HashMap<String, (git2::Oid, Summary)>>,
+    /// Why the commit's `taster.toml` could not be used, if it was invalid.
+    pub config_error: Option<String>,
 }
 
-fn run_benchmark(workdir: &str, cfg: &Config, bench: &Benchmark, timeout: Option<u64>) -> Output {
+/// Runs `cmd` as `stage`, whose output is written to `logs` as it is produced.
+fn run_stage(
+    cmd: &mut Command,
+    stage: &str,
+    timeout: Option<Duration>,
+    logs: &CommitLogs,
+    cancel: &CancelToken,
+) -> io::Result<Finished> {
+    let finished = process::run(cmd, timeout, logs.tee(stage), cancel)?;
+    logs.finish(stage, &finished);
+    Ok(finished)
+}
+
+fn run_benchmark(
+    workdir: &str,
+    cfg: &Config,
+    bench: &Benchmark,
+    stage: &str,
+    metrics_file: Option<&MetricsFile>,
+    timeout: Option<Duration>,
+    logs: &CommitLogs,
+    cancel: &CancelToken,
+) -> Result<Finished, String> {
     let mut cmd = if cfg.version.is_none() || cfg.version.unwrap() < 2 {
         // older taster configs assume an implied "cargo" prefix on each benchmark command
-        match timeout {
-            None => {
-                let mut cmd = Command::new("cargo");
-                cmd.arg(&bench.cmd);
-                cmd
-            }
-            Some(timeout) => {
-                let mut cmd = Command::new("timeout");
-                cmd.arg("-k")
-                    .arg(&format!("{}s", timeout + 30))
-                    .arg(&format!("{}s", timeout))
-                    .arg("cargo")
-                    .arg(&bench.cmd);
-                cmd
-            }
-        }
+        let mut cmd = Command::new("cargo");
+        cmd.arg(&bench.cmd);
+        cmd
     } else {
         // from taster config version 2, we no longer assume an implicit "cargo" prefix on
         // benchmark commands
-        match timeout {
-            None => Command::new(&bench.cmd),
-            Some(timeout) => {
-                let mut cmd = Command::new("timeout");
-                cmd.arg("-k")
-                    .arg(&format!("{}s", timeout + 30))
-                    .arg(&format!("{}s", timeout))
-                    .arg(&bench.cmd);
-                cmd
-            }
-        }
+        Command::new(&bench.cmd)
     };
 
     cmd.current_dir(workdir)
         .env("RUST_BACKTRACE", "1")
         .args(bench.args.as_slice());
+    if let Some(f) = metrics_file {
+        cmd.env(extract::METRICS_FILE_VAR, &f.path);
+    }
+
+    let finished = run_stage(&mut cmd, stage, timeout, logs, cancel)
+        .map_err(|e| format!("Failed to execute benchmark '{}': {}", bench.name, e))?;
+    if !finished.outcome.success() {
+        println!("benchmark {} {}", bench.name, finished.outcome);
+    }
+    Ok(finished)
+}
+
+/// Extracts the metrics reported by a single benchmark run in `workdir`, which started at
+/// `started`, from its output and, depending on the benchmark's format, the result files it
+/// wrote or its `metrics_file`.
+fn extract_metrics(
+    bench: &Benchmark,
+    workdir: &str,
+    output: &Finished,
+    started: SystemTime,
+    metrics_file: Option<&MetricsFile>,
+) -> HashMap<String, Measurement> {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut res = extract::metrics(bench, stdout.lines().chain(stderr.lines()));
+    if bench.format == OutputFormat::Criterion {
+        res.extend(extract::criterion(bench, Path::new(workdir), started));
+    }
+    if let Some(f) = metrics_file {
+        res.extend(f.read(bench));
+    }
+    res
+}
+
+/// Classifies `new` against the baseline samples in `old`. If both sides have several samples,
+/// a change is only reported if it is statistically significant as well as exceeding the
+/// benchmark's thresholds. Otherwise, if the benchmark reported a confidence interval for `new`
+/// (as Criterion does), the baseline must lie outside of it.
+fn classify(
+    bench: &Benchmark,
+    lower_is_better: bool,
+    new: Summary,
+    old: &Summary,
+) -> BenchmarkResult<Summary> {
+    if old.mean == 0.0 {
+        // a relative change from zero is meaningless, and would be infinite or NaN
+        return BenchmarkResult::Neutral(new, 0.0);
+    }
+    let change = (new.mean / old.mean) - 1.0;
+    let significant = match stats::welch_t_test(&new, old) {
+        Some(p) => p < bench.significance,
+        None => match new.reported_ci {
+            Some((lo, hi)) if bench.criterion_ci => old.mean < lo || old.mean > hi,
+            _ => true,
+        },
+    };
+    let (improved, regressed) = if lower_is_better {
+        (
+            change < -bench.improvement_threshold,
+            change >= bench.regression_threshold,
+        )
+    } else {
+        (
+            change >= bench.improvement_threshold,
+            change < -bench.regression_threshold,
+        )
+    };
 
-    cmd.output()
-        .expect(&format!("Failed to execute benchmark '{}'!", bench.name))
+    if significant && regressed {
+        BenchmarkResult::Regression(new, change)
+    } else if significant && improved {
+        BenchmarkResult::Improvement(new, change)
+    } else {
+        BenchmarkResult::Neutral(new, change)
+    }
 }
 
-fn write_output(output: &Output, commit_id: git2::Oid, name: &str) {
-    use std::fs::File;
-    use std::io::Write;
-
-    let mut stdout_file =
-        File::create(&format!("{}-{}-stdout.log", commit_id, name)).expect(&format!(
-            "Failed to create stdout log file for '{}' at commit '{}'.",
-            name, commit_id
-        ));
-    stdout_file
-        .write_all(output.stdout.as_slice())
-        .expect("Failed to write output to stdout log file!");
-    let mut stderr_file =
-        File::create(&format!("{}-{}-stderr.log", commit_id, name)).expect(&format!(
-            "Failed to create stderr log file for '{}' at commit '{}'.",
-            name, commit_id
-        ));
-    stderr_file
-        .write_all(output.stderr.as_slice())
-        .expect("Failed to write output to stderr log file!");
+/// Returns a file for `stage`, a run of `bench`, to write its metrics to, if it uses the
+/// JSON-lines protocol.
+fn new_metrics_file(
+    bench: &Benchmark,
+    stage: &str,
+    logs: &CommitLogs,
+) -> Result<Option<MetricsFile>, String> {
+    if bench.format != OutputFormat::JsonLines {
+        return Ok(None);
+    }
+    logs.metrics_file(stage)
+        .map(Some)
+        .map_err(|e| format!("Failed to create metrics file for benchmark '{}': {}", bench.name, e))
 }
 
-fn benchmark(
+/// Runs `bench` in `workdir` and classifies each metric it reports against the samples that
+/// `baseline` returns for it. Metrics without a baseline are reported as neutral.
+pub fn benchmark<F>(
     workdir: &str,
     cfg: &Config,
     bench: &Benchmark,
-    commit_id: git2::Oid,
-    previous_result: Option<&HashMap<String, BenchmarkResult<f64>>>,
-    timeout: Option<u64>,
-) -> (ExitStatus, HashMap<String, BenchmarkResult<f64>>) {
-    // Run the benchmark and collect its output
-    let output = run_benchmark(workdir, cfg, bench, timeout);
-    write_output(&output, commit_id, &bench.name);
-
-    let lines = str::from_utf8(output.stdout.as_slice())
-        .unwrap()
-        .lines()
-        .chain(str::from_utf8(output.stderr.as_slice()).unwrap().lines());
+    logs: &CommitLogs,
+    mut baseline: F,
+    timeout: Option<Duration>,
+    cancel: &CancelToken,
+) -> Result<(Outcome, HashMap<String, BenchmarkResult<Summary>>), String>
+where
+    F: FnMut(&str) -> Option<Vec<f64>>,
+{
     let mut res = HashMap::new();
 
-    // Don't try parsing the output if we didn't succeed
-    if !output.status.success() {
-        return (output.status, res);
+    // Warm up without looking at the results
+    for i in 0..bench.warmup_runs {
+        let stage = format!("bench-{}-warmup{}", bench.name, i);
+        let metrics_file = new_metrics_file(bench, &stage, logs)?;
+        let run = run_benchmark(
+            workdir,
+            cfg,
+            bench,
+            &stage,
+            metrics_file.as_ref(),
+            timeout,
+            logs,
+            cancel,
+        )?;
+        if !run.outcome.success() {
+            return Ok((run.outcome, res));
+        }
     }
 
-    // Success, so let's look for the results
-    for l in lines {
-        for (i, regex) in bench.result_expr.iter().enumerate() {
-            for cap in regex.captures_iter(l) {
-                let (metric, value) = if cap.len() > 2 {
-                    (String::from(cap.at(1).unwrap()), cap.at(2))
-                } else {
-                    (format!("{}", i), cap.at(1))
-                };
-                let bm_name = format!("{}/{}", bench.name, &metric);
-                if let Some(c) = value {
-                    use std::str::FromStr;
-                    let val = match f64::from_str(&c) {
-                        Ok(f) => f,
-                        Err(_) => {
-                            println!(
-                                "failed to parse value '{}' for {} into f64 number, ignoring",
-                                c, bm_name
-                            );
-                            continue;
-                        }
-                    };
-                    let new_result = match previous_result {
-                        None => BenchmarkResult::Improvement(val, 0.0),
-                        Some(prev_res) => {
-                            let old_val = match prev_res.get(&bm_name) {
-                                None => val,
-                                Some(pv) => match *pv {
-                                    BenchmarkResult::Improvement(v, _) => v,
-                                    BenchmarkResult::Regression(v, _) => v,
-                                    BenchmarkResult::Neutral(v, _) => v,
-                                },
-                            };
-                            let new_result = if bench.lower_is_better {
-                                if val >= old_val * (1.0 + bench.regression_threshold) {
-                                    BenchmarkResult::Regression(val, (val / old_val) - 1.0)
-                                } else if val < old_val * (1.0 - bench.improvement_threshold) {
-                                    BenchmarkResult::Improvement(val, (val / old_val) - 1.0)
-                                } else {
-                                    BenchmarkResult::Neutral(val, (val / old_val) - 1.0)
-                                }
-                            } else {
-                                if val >= old_val * (1.0 + bench.improvement_threshold) {
-                                    BenchmarkResult::Improvement(val, (val / old_val) - 1.0)
-                                } else if val < old_val * (1.0 - bench.regression_threshold) {
-                                    BenchmarkResult::Regression(val, (val / old_val) - 1.0)
-                                } else {
-                                    BenchmarkResult::Neutral(val, (val / old_val) - 1.0)
-                                }
-                            };
-                            new_result
-                        }
-                    };
-                    res.insert(bm_name, new_result);
-                }
-            }
+    // Run the benchmark the requested number of times and collect samples for each metric
+    let runs = cmp::max(bench.runs, 1);
+    let mut samples: HashMap<String, Vec<Measurement>> = HashMap::new();
+    for i in 0..runs {
+        let stage = if runs > 1 {
+            format!("bench-{}-run{}", bench.name, i)
+        } else {
+            format!("bench-{}", bench.name)
+        };
+        let metrics_file = new_metrics_file(bench, &stage, logs)?;
+        let started = SystemTime::now();
+        let run = run_benchmark(
+            workdir,
+            cfg,
+            bench,
+            &stage,
+            metrics_file.as_ref(),
+            timeout,
+            logs,
+            cancel,
+        )?;
+
+        // Don't try parsing the output if we didn't succeed
+        if !run.outcome.success() {
+            return Ok((run.outcome, res));
+        }
+        let mut metrics = extract_metrics(bench, workdir, &run, started, metrics_file.as_ref());
+        if !bench.result_files.is_empty() {
+            let (found, files) = extract::result_files(bench, Path::new(workdir), started);
+            logs.archive(&stage, Path::new(workdir), &files);
+            metrics.extend(found);
+        }
+        for (metric, m) in metrics {
+            samples.entry(metric).or_insert(Vec::new()).push(m.normalized());
         }
     }
-    (output.status, res)
+
+    for (bm_name, ms) in samples {
+        let lower_is_better = ms.iter()
+            .filter_map(|m| m.lower_is_better)
+            .next()
+            .unwrap_or(bench.lower_is_better);
+        let mut summary = Summary::new(ms.iter().map(|m| m.value).collect());
+        summary.unit = ms.iter().filter_map(|m| m.unit.clone()).next();
+        let deviations = ms.iter().filter_map(|m| m.deviation).collect::<Vec<_>>();
+        if !deviations.is_empty() {
+            summary.reported_deviation =
+                Some(deviations.iter().sum::<f64>() / deviations.len() as f64);
+        }
+        let cis = ms.iter().filter_map(|m| m.ci).collect::<Vec<_>>();
+        if !cis.is_empty() {
+            let n = cis.len() as f64;
+            summary.reported_ci = Some((
+                cis.iter().map(|ci| ci.0).sum::<f64>() / n,
+                cis.iter().map(|ci| ci.1).sum::<f64>() / n,
+            ));
+        }
+        let new_result = match baseline(&bm_name) {
+            None => BenchmarkResult::Neutral(summary, 0.0),
+            Some(old) => classify(bench, lower_is_better, summary, &Summary::new(old)),
+        };
+        res.insert(bm_name, new_result);
+    }
+    Ok((Outcome::Success, res))
 }
 
-fn build(workdir: &str) -> Output {
-    Command::new("cargo")
-        .current_dir(workdir)
+fn build(
+    workdir: &str,
+    timeout: Option<Duration>,
+    logs: &CommitLogs,
+    cancel: &CancelToken,
+) -> Result<Finished, String> {
+    let mut cmd = Command::new("cargo");
+    cmd.current_dir(workdir)
         .arg("check")
         .arg("--all")
         .arg("--all-targets")
-        .env("RUST_BACKTRACE", "1")
-        .output()
-        .expect("Failed to execute 'cargo build'!")
+        .env("RUST_BACKTRACE", "1");
+    run_stage(&mut cmd, "build", timeout, logs, cancel)
+        .map_err(|e| format!("Failed to execute 'cargo build': {}", e))
+}
+
+/// Runs `cargo update` in `workdir` if it doesn't have a `Cargo.lock` yet, and then builds it.
+pub fn update_and_build(
+    workdir: &str,
+    timeout: Option<Duration>,
+    logs: &CommitLogs,
+    cancel: &CancelToken,
+) -> Result<Outcome, String> {
+    let do_update = !Path::new(&format!("{}/Cargo.lock", workdir)).exists();
+
+    let update_outcome = if do_update {
+        println!("running 'cargo update'");
+        let updated = update(workdir, timeout, logs, cancel)?;
+        if !updated.outcome.success() {
+            println!("update {}", updated.outcome);
+        }
+        updated.outcome
+    } else {
+        // nothing to do, always succeeds
+        Outcome::Success
+    };
+
+    let built = build(workdir, timeout, logs, cancel)?;
+    if !built.outcome.success() {
+        println!("build {}", built.outcome);
+    }
+
+    // a failed update is reported in preference to whatever the build made of it
+    if update_outcome.success() {
+        Ok(built.outcome)
+    } else {
+        Ok(update_outcome)
+    }
+}
+
+/// Bails out of a tasting if its job has been cancelled, e.g. because it was superseded.
+fn check_cancelled(cancel: &CancelToken, commit: &Commit) -> Result<(), String> {
+    if cancel.is_cancelled() {
+        Err(format!("tasting of {} was cancelled", commit.id))
+    } else {
+        Ok(())
+    }
+}
+
+/// Maximum number of first-parent ancestors to inspect when looking for a baseline.
+const MAX_BASELINE_DEPTH: usize = 1000;
+
+/// Follows the first-parent chain from (and including) `start` to the nearest commit that has
+/// results for `metric` of `bench`.
+fn nearest_tasted<'a>(
+    repo: &git2::Repository,
+    history: &'a History,
+    start: git2::Oid,
+    bench: &str,
+    metric: &str,
+) -> Option<(git2::Oid, &'a Vec<f64>)> {
+    let mut cur = start;
+    for _ in 0..MAX_BASELINE_DEPTH {
+        if let Some(res) = history.results_for(&cur.to_string(), bench, metric) {
+            return Some((cur, res));
+        }
+        cur = match repo.find_commit(cur).and_then(|c| c.parent_id(0)) {
+            Ok(p) => p,
+            Err(_) => return None,
+        };
+    }
+    None
+}
+
+/// Picks the results that `commit` is compared against for `metric` of `bench`. Pull requests
+/// are compared against the nearest tasted ancestor of their merge-base with the target branch;
+/// other commits against their nearest tasted first-parent ancestor, falling back to the fork
+/// point on `default_branch`.
+fn baseline<'a>(
+    ws: &Workspace,
+    history: &'a History,
+    push: &Push,
+    commit: &Commit,
+    default_branch: &str,
+    bench: &str,
+    metric: &str,
+) -> Option<(git2::Oid, &'a Vec<f64>)> {
+    let repo = &ws.repo;
+    let fork_point = |branch_ref: &str| -> Option<git2::Oid> {
+        let head = repo.revparse_single(branch_ref).ok()?.id();
+        repo.merge_base(commit.id, head).ok()
+    };
+
+    match push.base_ref {
+        Some(ref base) => {
+            let base = format!(
+                "refs/remotes/origin/{}",
+                base.trim_start_matches("refs/heads/")
+            );
+            // if the PR's head is already part of the base branch, it is its own merge base, and
+            // mustn't be compared against an earlier tasting of itself
+            fork_point(&base)
+                .filter(|mb| *mb != commit.id)
+                .and_then(|mb| nearest_tasted(repo, history, mb, bench, metric))
+        }
+        None => repo.find_commit(commit.id)
+            .and_then(|c| c.parent_id(0))
+            .ok()
+            .and_then(|p| nearest_tasted(repo, history, p, bench, metric))
+            .or_else(|| {
+                fork_point(&format!("refs/remotes/origin/{}", default_branch))
+                    .filter(|mb| *mb != commit.id)
+                    .and_then(|mb| nearest_tasted(repo, history, mb, bench, metric))
+            }),
+    }
+}
+
+/// Returns the samples of each metric in `metrics`, as they are kept in the history.
+fn samples(metrics: &HashMap<String, BenchmarkResult<Summary>>) -> HashMap<String, Vec<f64>> {
+    metrics
+        .iter()
+        .map(|(k, v)| {
+            let val = match *v {
+                BenchmarkResult::Improvement(ref s, _) => s,
+                BenchmarkResult::Regression(ref s, _) => s,
+                BenchmarkResult::Neutral(ref s, _) => s,
+            };
+            (k.clone(), val.samples.clone())
+        })
+        .collect()
+}
+
+fn record(history: &mut History, push: &Push, res: &TastingResult) {
+    let benchmarks = match res.results {
+        None => HashMap::new(),
+        // failed benchmarks are left out so that they can't serve as baselines
+        Some(ref r) => r.iter()
+            .filter(|&&(_, ref status, _)| status.success())
+            .map(|&(ref b, _, ref metrics)| (b.name.clone(), samples(metrics)))
+            .collect(),
+    };
+    let tasting = Tasting {
+        repo: String::from(history.repo()),
+        push_ref: push.push_ref.clone(),
+        commit: res.commit.id.to_string(),
+        build: res.build.success(),
+        test: res.test.success(),
+        bench: res.bench,
+        timestamp: history::now(),
+        benchmarks: benchmarks,
+    };
+    if let Err(e) = history.record(tasting) {
+        println!("failed to record tasting of {}: {}", res.commit.id, e);
+    }
+}
+
+/// Records the results of a successful run of `bench` at `commit` outside of a full tasting
+/// (e.g., while bisecting), so that they can serve as baselines later on. Such commits are built
+/// but not tested.
+pub fn record_benchmark(
+    history: &mut History,
+    commit: git2::Oid,
+    bench: &Benchmark,
+    metrics: &HashMap<String, BenchmarkResult<Summary>>,
+) {
+    let mut benchmarks = HashMap::new();
+    benchmarks.insert(bench.name.clone(), samples(metrics));
+    let tasting = Tasting {
+        repo: String::from(history.repo()),
+        push_ref: None,
+        commit: commit.to_string(),
+        build: true,
+        test: false,
+        bench: true,
+        timestamp: history::now(),
+        benchmarks: benchmarks,
+    };
+    if let Err(e) = history.record(tasting) {
+        println!("failed to record results of {} for {}: {}", bench.name, commit, e);
+    }
 }
 
+/// Tastes `commit` in `ws`. `history` may be shared with other tastings running concurrently;
+/// if `bench_lock` is given, it is held while running benchmarks so that they don't compete for
+/// resources with other tastings.
 pub fn taste_commit(
     ws: &Workspace,
-    history: &mut HashMap<String, HashMap<String, HashMap<String, BenchmarkResult<f64>>>>,
+    history: &Mutex<History>,
     push: &Push,
     commit: &Commit,
+    default_branch: &str,
     def_improvement_threshold: f64,
     def_regression_threshold: f64,
-    timeout: Option<u64>,
+    timeouts: &Timeouts,
+    logs: &LogStore,
+    cancel: &CancelToken,
+    bench_lock: Option<&Mutex<()>>,
 ) -> Result<(Option<Config>, TastingResult), String> {
     println!("Tasting commit {}", commit.id);
     ws.checkout_commit(&commit.id)?;
+    let logs = logs.for_commit(
+        &ws.remote_url,
+        push.push_ref.as_ref().map(|r| r.as_str()),
+        commit.id,
+    );
 
     let branch = match push.push_ref {
         None => None,
+        Some(ref pr) if pr.starts_with("refs/pull/") => {
+            // refs/pull/<n>/head
+            Some(format!("PR #{}", pr.split('/').nth(2).unwrap_or("?")))
+        }
         Some(ref pr) => match pr.rfind("/") {
             None => None,
             Some(i) => Some(String::from(&pr[i + 1..])),
         },
     };
 
-    let version_output = version(&ws.path);
-    write_output(&version_output, commit.id, "version");
-
-    let do_update = !Path::new(&format!("{}/Cargo.lock", ws.path)).exists();
-
-    let build_success = {
-        let update_success = if do_update {
-            println!("running 'cargo update'");
-            let update_output = update(&ws.path);
-            write_output(&update_output, commit.id, "update");
-            if !update_output.status.success() {
-                println!("update failed: output status is {:?}", update_output.status);
-            }
-            update_output.status.success()
-        } else {
-            // nothing to do, always succeeds
-            true
-        };
+    version(&ws.path, &logs, cancel)?;
 
-        let build_output = build(&ws.path);
-        write_output(&build_output, commit.id, "build");
-        if !build_output.status.success() {
-            println!("build failed: output status is {:?}", build_output.status);
-        }
+    let build_outcome = update_and_build(&ws.path, timeouts.build, &logs, cancel)?;
 
-        update_success && build_output.status.success()
-    };
+    check_cancelled(cancel, commit)?;
 
-    let test_output = test(&ws.path, timeout);
-    write_output(&test_output, commit.id, "test");
+    let tested = test(&ws.path, timeouts.test, &logs, cancel)?;
+    check_cancelled(cancel, commit)?;
 
-    if !test_output.status.success() {
-        println!("tests failed: output status is {:?}", test_output.status);
+    if !tested.outcome.success() {
+        println!("tests {}", tested.outcome);
     }
 
     let cfg = match parse_config(
@@ -249,125 +519,110 @@ pub fn taste_commit(
         def_regression_threshold,
     ) {
         Ok(c) => c,
-        Err(e) => match e.kind() {
-            io::ErrorKind::NotFound => {
-                println!(
-                    "Skipping commit {} which doesn't have a Taster config.",
-                    commit.id
-                );
-                return Ok((
-                    None,
-                    TastingResult {
-                        branch: branch,
-                        commit: commit.clone(),
-                        build: build_success,
-                        test: test_output.status.success(),
-                        bench: false,
-                        results: None,
-                    },
-                ));
-            }
-            io::ErrorKind::InvalidInput => {
-                println!(
-                    "Skipping commit {} which has an invalid Taster config.",
-                    commit.id
-                );
-                return Ok((
-                    None,
-                    TastingResult {
-                        branch: branch,
-                        commit: commit.clone(),
-                        build: build_success,
-                        test: test_output.status.success(),
-                        bench: false,
-                        results: None,
-                    },
-                ));
-            }
-            _ => unimplemented!(),
-        },
-    };
-
-    let bench_results = match branch {
-        Some(ref branch) => {
-            let branch_history = history.entry(branch.clone()).or_insert(HashMap::new());
-            cfg.benchmarks
-                .iter()
-                .map(|b| {
-                    let (status, res) = benchmark(
-                        &ws.path,
-                        &cfg,
-                        b,
-                        commit.id,
-                        branch_history.get(&b.name),
-                        timeout,
+        Err(e) => {
+            let config_error = match e.kind() {
+                io::ErrorKind::NotFound => {
+                    println!(
+                        "Skipping commit {} which doesn't have a Taster config.",
+                        commit.id
+                    );
+                    None
+                }
+                io::ErrorKind::InvalidInput => {
+                    println!(
+                        "Skipping commit {} which has an invalid Taster config: {}",
+                        commit.id, e
                     );
-                    branch_history.insert(b.name.clone(), res.clone());
-                    (b.clone(), status, res)
-                })
-                .collect::<Vec<(Benchmark, ExitStatus, HashMap<String, BenchmarkResult<f64>>)>>()
+                    Some(format!("{}", e))
+                }
+                _ => return Err(format!("Failed to read taster.toml: {}", e)),
+            };
+            let tr = TastingResult {
+                branch: branch,
+                commit: commit.clone(),
+                build: build_outcome,
+                test: tested.outcome,
+                bench: false,
+                results: None,
+                baselines: HashMap::new(),
+                config_error: config_error,
+            };
+            record(&mut history.lock().unwrap(), push, &tr);
+            return Ok((None, tr));
         }
-        None => cfg
-            .benchmarks
-            .iter()
-            .map(|b| {
-                let (status, res) = benchmark(&ws.path, &cfg, b, commit.id, None, timeout);
-                (b.clone(), status, res)
-            })
-            .collect::<Vec<(Benchmark, ExitStatus, HashMap<String, BenchmarkResult<f64>>)>>(),
     };
+
+    let mut bench_results = Vec::new();
+    let mut baselines = HashMap::new();
+    // the lock guards no data, so it's still usable if a tasting panicked while holding it
+    let _bench_guard = bench_lock.map(|l| l.lock().unwrap_or_else(|e| e.into_inner()));
+    for b in cfg.benchmarks.iter() {
+        let mut compared = HashMap::new();
+        let (status, res) = {
+            let find_baseline = |metric: &str| -> Option<Vec<f64>> {
+                let history = history.lock().unwrap();
+                let (oid, samples) =
+                    baseline(ws, &history, push, commit, default_branch, &b.name, metric)?;
+                println!("comparing {} results for {} against {}", metric, commit.id, oid);
+                compared.insert(String::from(metric), (oid, Summary::new(samples.clone())));
+                Some(samples.clone())
+            };
+            benchmark(&ws.path, &cfg, b, &logs, find_baseline, timeouts.bench, cancel)?
+        };
+        check_cancelled(cancel, commit)?;
+        if !compared.is_empty() {
+            baselines.insert(b.name.clone(), compared);
+        }
+        bench_results.push((b.clone(), status, res));
+    }
     let bench_success = bench_results.iter().all(|x| x.1.success());
 
-    Ok((
-        Some(cfg),
-        TastingResult {
-            branch: branch,
-            commit: commit.clone(),
-            build: build_success,
-            test: test_output.status.success(),
-            bench: bench_success,
-            results: Some(bench_results),
-        },
-    ))
+    let tr = TastingResult {
+        branch: branch,
+        commit: commit.clone(),
+        build: build_outcome,
+        test: tested.outcome,
+        bench: bench_success,
+        results: Some(bench_results),
+        baselines: baselines,
+        config_error: None,
+    };
+    record(&mut history.lock().unwrap(), push, &tr);
+
+    Ok((Some(cfg), tr))
 }
 
-fn test(workdir: &str, timeout: Option<u64>) -> Output {
-    let mut cmd = match timeout {
-        None => {
-            let mut cmd = Command::new("cargo");
-            cmd.arg("test").arg("--all");
-            cmd
-        }
-        Some(timeout) => {
-            let mut cmd = Command::new("timeout");
-            cmd.arg("-k")
-                .arg(&format!("{}s", timeout + 30))
-                .arg(&format!("{}s", timeout))
-                .arg("cargo")
-                .arg("test")
-                .arg("--all");
-            cmd
-        }
-    };
+fn test(
+    workdir: &str,
+    timeout: Option<Duration>,
+    logs: &CommitLogs,
+    cancel: &CancelToken,
+) -> Result<Finished, String> {
+    let mut cmd = Command::new("cargo");
     cmd.current_dir(workdir)
+        .arg("test")
+        .arg("--all")
         .env("RUST_BACKTRACE", "1")
-        .env("RUST_TEST_THREADS", "1")
-        .output()
-        .expect("Failed to execute 'cargo test'!")
+        .env("RUST_TEST_THREADS", "1");
+    run_stage(&mut cmd, "test", timeout, logs, cancel)
+        .map_err(|e| format!("Failed to execute 'cargo test': {}", e))
 }
 
-fn update(workdir: &str) -> Output {
-    Command::new("cargo")
-        .current_dir(workdir)
-        .arg("update")
-        .output()
-        .expect("Failed to execute 'cargo update'!")
+fn update(
+    workdir: &str,
+    timeout: Option<Duration>,
+    logs: &CommitLogs,
+    cancel: &CancelToken,
+) -> Result<Finished, String> {
+    let mut cmd = Command::new("cargo");
+    cmd.current_dir(workdir).arg("update");
+    run_stage(&mut cmd, "update", timeout, logs, cancel)
+        .map_err(|e| format!("Failed to execute 'cargo update': {}", e))
 }
 
-fn version(workdir: &str) -> Output {
-    Command::new("rustc")
-        .current_dir(workdir)
-        .arg("--version")
-        .output()
-        .expect("Failed to execute 'rustc --version'!")
+fn version(workdir: &str, logs: &CommitLogs, cancel: &CancelToken) -> Result<Finished, String> {
+    let mut cmd = Command::new("rustc");
+    cmd.current_dir(workdir).arg("--version");
+    run_stage(&mut cmd, "version", None, logs, cancel)
+        .map_err(|e| format!("Failed to execute 'rustc --version': {}", e))
 }
@@ -0,0 +1,308 @@
+use hmac::{Hmac, Mac};
+use hyper::Client;
+use hyper::header::{ContentType, Headers};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use serde_json;
+use sha2::Sha256;
+use std::thread;
+use std::time::Duration;
+
+use Commit;
+use Push;
+use config::Config;
+use notifier::Notifier;
+use taste::{BenchmarkResult, TastingResult};
+
+/// Version of the JSON document we POST; bump this whenever its structure changes incompatibly.
+const PAYLOAD_VERSION: u32 = 1;
+
+/// Number of delivery attempts per URL before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Serialize)]
+struct MetricReport {
+    name: String,
+    value: f64,
+    /// Unit of `value`, `samples`, and `baseline`, after normalization.
+    unit: Option<String>,
+    samples: Vec<f64>,
+    /// Mean of the baseline's samples, if there is one.
+    baseline: Option<f64>,
+    baseline_commit: Option<String>,
+    /// Relative change of `value` against `baseline`.
+    delta: Option<f64>,
+    classification: &'static str,
+}
+
+#[derive(Serialize)]
+struct BenchmarkReport {
+    name: String,
+    success: bool,
+    metrics: Vec<MetricReport>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    version: u32,
+    repo: String,
+    commit: String,
+    commit_message: String,
+    commit_url: String,
+    branch: Option<String>,
+    push_ref: Option<String>,
+    pusher: Option<String>,
+    build: bool,
+    test: bool,
+    bench: bool,
+    config_error: Option<String>,
+    benchmarks: Vec<BenchmarkReport>,
+}
+
+/// Posts a JSON description of each tasting to a set of URLs.
+pub struct WebhookNotifier {
+    urls: Vec<String>,
+    secret: Option<String>,
+    repo: String,
+}
+
+/// Hex-encoded HMAC-SHA256 of `body` under `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    // HMAC takes keys of any length, so this can't fail
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn deliver(url: &str, body: &str, signature: Option<&str>) -> Result<(), String> {
+    let ssl = NativeTlsClient::new().map_err(|e| format!("{}", e))?;
+    let client = Client::with_connector(HttpsConnector::new(ssl));
+    let mut headers = Headers::new();
+    headers.set(ContentType::json());
+    if let Some(sig) = signature {
+        headers.set_raw(
+            "X-Taster-Signature",
+            vec![format!("sha256={}", sig).into_bytes()],
+        );
+    }
+
+    let res = client
+        .post(url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .map_err(|e| format!("{}", e))?;
+    if res.status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("server responded with {}", res.status))
+    }
+}
+
+impl WebhookNotifier {
+    pub fn new(urls: &[&str], secret: Option<&str>, repo_url: &str) -> WebhookNotifier {
+        WebhookNotifier {
+            urls: urls.iter().map(|u| String::from(*u)).collect(),
+            secret: secret.map(String::from),
+            repo: String::from(repo_url),
+        }
+    }
+
+    fn report(&self, res: &TastingResult, push: &Push, commit: &Commit) -> Report {
+        let benchmarks = match res.results {
+            None => vec![],
+            Some(ref r) => r.iter()
+                .map(|&(ref bm, ref status, ref metrics)| {
+                    let baselines = res.baselines.get(&bm.name);
+                    let mut metrics = metrics
+                        .iter()
+                        .map(|(k, v)| {
+                            let baseline = baselines.and_then(|b| b.get(k));
+                            let (summary, delta, classification) = match *v {
+                                BenchmarkResult::Improvement(ref s, d) => (s, d, "improvement"),
+                                BenchmarkResult::Neutral(ref s, d) => (s, d, "neutral"),
+                                BenchmarkResult::Regression(ref s, d) => (s, d, "regression"),
+                            };
+                            MetricReport {
+                                name: k.clone(),
+                                value: summary.mean,
+                                unit: summary.unit.clone(),
+                                samples: summary.samples.clone(),
+                                baseline: baseline.map(|&(_, ref s)| s.mean),
+                                baseline_commit: baseline.map(|&(oid, _)| oid.to_string()),
+                                delta: baseline.map(|_| delta),
+                                classification: classification,
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+                    BenchmarkReport {
+                        name: bm.name.clone(),
+                        success: status.success(),
+                        metrics: metrics,
+                    }
+                })
+                .collect(),
+        };
+
+        Report {
+            version: PAYLOAD_VERSION,
+            repo: self.repo.clone(),
+            commit: commit.id.to_string(),
+            commit_message: commit.msg.clone(),
+            commit_url: commit.url.clone(),
+            branch: res.branch.clone(),
+            push_ref: push.push_ref.clone(),
+            pusher: push.pusher.clone(),
+            build: res.build.success(),
+            test: res.test.success(),
+            bench: res.bench,
+            config_error: res.config_error.clone(),
+            benchmarks: benchmarks,
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    /// Delivers the report for `res` to every configured URL. Delivery happens in the
+    /// background, retrying with exponential backoff, so this only fails if the report cannot
+    /// be serialized.
+    fn notify(
+        &self,
+        _cfg: Option<&Config>,
+        res: &TastingResult,
+        push: &Push,
+        commit: &Commit,
+    ) -> Result<(), String> {
+        let body = serde_json::to_string(&self.report(res, push, commit))
+            .map_err(|e| format!("failed to serialize webhook payload: {}", e))?;
+        let signature = self.secret.as_ref().map(|s| sign(s, body.as_bytes()));
+
+        for url in self.urls.iter() {
+            let url = url.clone();
+            let body = body.clone();
+            let signature = signature.clone();
+            thread::spawn(move || {
+                let mut backoff = Duration::from_secs(1);
+                for attempt in 1..MAX_ATTEMPTS + 1 {
+                    match deliver(&url, &body, signature.as_ref().map(|s| s.as_str())) {
+                        Ok(()) => return,
+                        Err(e) => println!(
+                            "webhook delivery to {} failed (attempt {}/{}): {}",
+                            url, attempt, MAX_ATTEMPTS, e
+                        ),
+                    }
+                    if attempt < MAX_ATTEMPTS {
+                        thread::sleep(backoff);
+                        backoff *= 2;
+                    }
+                }
+                println!("giving up on webhook delivery to {}", url);
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Benchmark, OutputFormat};
+    use git2::Oid;
+    use process::Outcome;
+    use stats::Summary;
+    use std::collections::HashMap;
+
+    #[test]
+    fn signature_known_answer() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn report() {
+        let bench = Benchmark {
+            name: String::from("bench"),
+            cmd: String::from("true"),
+            args: vec![],
+            format: OutputFormat::Regex,
+            result_expr: vec![],
+            result_files: vec![],
+            lower_is_better: true,
+            improvement_threshold: 0.05,
+            regression_threshold: 0.05,
+            runs: 1,
+            warmup_runs: 0,
+            significance: 0.05,
+            criterion_ci: false,
+        };
+        let mut metrics = HashMap::new();
+        metrics.insert(
+            String::from("time"),
+            BenchmarkResult::Regression(Summary::new(vec![3.0]), 0.5),
+        );
+        metrics.insert(
+            String::from("new"),
+            BenchmarkResult::Neutral(Summary::new(vec![1.0]), 0.0),
+        );
+        let base = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let mut baselines = HashMap::new();
+        baselines.insert(String::from("time"), (base, Summary::new(vec![2.0])));
+        let mut all_baselines = HashMap::new();
+        all_baselines.insert(String::from("bench"), baselines);
+
+        let commit = Commit {
+            id: Oid::from_str("2222222222222222222222222222222222222222").unwrap(),
+            msg: String::from("make it \"fast\""),
+            url: String::from("https://github.com/o/r/commit/2222"),
+        };
+        let push = Push {
+            head_commit: commit.clone(),
+            push_ref: Some(String::from("refs/heads/master")),
+            base_ref: None,
+            pusher: Some(String::from("alice")),
+            owner_name: Some(String::from("o")),
+            repo_name: Some(String::from("r")),
+        };
+        let res = TastingResult {
+            branch: Some(String::from("master")),
+            commit: commit.clone(),
+            build: Outcome::Success,
+            test: Outcome::Success,
+            bench: true,
+            results: Some(vec![(bench, Outcome::Success, metrics)]),
+            baselines: all_baselines,
+            config_error: None,
+        };
+
+        let notifier = WebhookNotifier::new(&[], Some("key"), "https://github.com/o/r");
+        let body = serde_json::to_string(&notifier.report(&res, &push, &commit)).unwrap();
+        assert_eq!(
+            body,
+            concat!(
+                r#"{"version":1,"repo":"https://github.com/o/r","#,
+                r#""commit":"2222222222222222222222222222222222222222","#,
+                r#""commit_message":"make it \"fast\"","#,
+                r#""commit_url":"https://github.com/o/r/commit/2222","#,
+                r#""branch":"master","push_ref":"refs/heads/master","pusher":"alice","#,
+                r#""build":true,"test":true,"bench":true,"config_error":null,"#,
+                r#""benchmarks":[{"name":"bench","success":true,"metrics":["#,
+                r#"{"name":"new","value":1.0,"unit":null,"samples":[1.0],"baseline":null,"#,
+                r#""baseline_commit":null,"delta":null,"classification":"neutral"},"#,
+                r#"{"name":"time","value":3.0,"unit":null,"samples":[3.0],"baseline":2.0,"#,
+                r#""baseline_commit":"1111111111111111111111111111111111111111","#,
+                r#""delta":0.5,"classification":"regression"}]}]}"#
+            )
+        );
+    }
+}